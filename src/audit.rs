@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context as _, Error};
+use chrono::{DateTime, Local};
+use poise::serenity_prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    data::{self, GuildData},
+    utilities::format_datetime,
+    Data, PoiseContext, Task,
+};
+
+/// 履歴を何件まで残すか
+const HISTORY_LIMIT: usize = 1000;
+
+// 自動で更新される記録や、メンバー個人のデータは変更履歴に含めない
+const EXCLUDED: [&str; 9] = [
+    "sent_reminders",
+    "sent_deadline_reminders",
+    "last_notify",
+    "last_backup",
+    "completed",
+    "subscriptions",
+    "audit_log",
+    "trash",
+    "calendar_token",
+];
+const TASK_PREFIX: &str = "tasks/";
+
+pub type Snapshot = BTreeMap<String, Value>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    /// 設定の項目名、またはタスクなら`tasks/<ID>`
+    pub key: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub at: DateTime<Local>,
+    pub user: UserId,
+    /// 変更したコマンドなどの名前
+    pub action: String,
+    pub changes: Vec<Change>,
+    /// `/undo`で取り消した変更か、取り消しの操作そのものであればtrue。続けて取り消すときに飛ばす
+    #[serde(default)]
+    pub undone: bool,
+}
+
+impl Entry {
+    pub fn describe(&self) -> Vec<String> {
+        self.changes.iter().map(describe).collect()
+    }
+}
+
+/// 変更履歴の対象になるデータを、タスクは1件ずつに分けて取り出します。
+/// 個人のタスクは持ち主以外に見せないので含めません。
+pub fn snapshot(guild: &GuildData) -> Result<Snapshot, Error> {
+    let Value::Object(guild) = serde_json::to_value(guild)? else {
+        unreachable!("GuildData is always serialized as an object");
+    };
+    let mut snapshot = Snapshot::new();
+    for (key, value) in guild {
+        if EXCLUDED.contains(&key.as_str()) {
+            continue;
+        }
+        match (key.as_str(), value) {
+            ("tasks", Value::Array(tasks)) => {
+                for task in tasks.into_iter().filter(|t| t["owner"].is_null()) {
+                    let id = task["id"].as_str().context("Task without id")?.to_string();
+                    snapshot.insert(format!("{}{}", TASK_PREFIX, id), task);
+                }
+            }
+            (_, value) => {
+                snapshot.insert(key, value);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<Change> {
+    let mut keys = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| Change {
+            key: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// 前回記録したときからの変更を比べる基準を、今のデータにします。読み込み直したときに使います。
+pub fn reset(guild: &GuildData) -> Result<(), Error> {
+    let snapshot = snapshot(guild)?;
+    guild.audit_baseline.lock().unwrap().replace(snapshot);
+    Ok(())
+}
+
+/// コマンドでの変更を記録してから保存します。
+pub async fn save(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    record(
+        ctx.serenity_context(),
+        ctx.data(),
+        &guild,
+        ctx.author(),
+        &ctx.command().qualified_name,
+        false,
+    )
+    .await
+}
+
+/// `/undo`での変更を記録してから保存します。この記録は続けて取り消すときに飛ばします。
+pub async fn save_undo(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    record(
+        ctx.serenity_context(),
+        ctx.data(),
+        &guild,
+        ctx.author(),
+        &ctx.command().qualified_name,
+        true,
+    )
+    .await
+}
+
+/// 前回記録したときからの変更を履歴に残し、保存してからログチャンネルにも送ります。
+async fn record(
+    ctx: &Context,
+    data: &Data,
+    guild: &GuildData,
+    user: &User,
+    action: &str,
+    undone: bool,
+) -> Result<(), Error> {
+    let after = snapshot(guild)?;
+    let before = guild
+        .audit_baseline
+        .lock()
+        .unwrap()
+        .replace(after.clone())
+        .map_or_else(|| snapshot(&GuildData::default()), Ok)?;
+    let changes = diff(&before, &after);
+
+    let entry = (!changes.is_empty()).then(|| Entry {
+        at: Local::now(),
+        user: user.id,
+        action: action.to_string(),
+        changes,
+        undone,
+    });
+    if let Some(entry) = &entry {
+        let mut history = guild.audit_log.lock().unwrap();
+        history.push(entry.clone());
+        let overflow = history.len().saturating_sub(HISTORY_LIMIT);
+        history.drain(..overflow);
+    }
+    data::save(data)?;
+
+    let log_channel = *guild.log_channel.lock().unwrap();
+    if let (Some(entry), Some(log_channel)) = (entry, log_channel) {
+        // 保存はできているので、ログを送れなくてもコマンドは失敗にしない
+        let result = log_channel
+            .send_message(
+                ctx,
+                CreateMessage::default().embed(
+                    CreateEmbed::default()
+                        .author(
+                            CreateEmbedAuthor::new(user.name.clone())
+                                .icon_url(user.avatar_url().unwrap_or_default()),
+                        )
+                        .title(format!("データの変更: {}", entry.action))
+                        .description(truncate(&entry.describe(), 4000))
+                        .timestamp(entry.at)
+                        .color(Color::DARK_BLUE),
+                ),
+            )
+            .await;
+        if let Err(error) = result {
+            println!("Failed to send audit log: {:#}", error);
+        }
+    }
+
+    Ok(())
+}
+
+/// 記録した変更を元に戻します。その後に同じ項目が変更されていれば、何もせずにその項目を返します。
+pub fn revert(guild: &GuildData, entry: &Entry) -> Result<Vec<String>, Error> {
+    let current = snapshot(guild)?;
+    let conflicts = entry
+        .changes
+        .iter()
+        .filter(|change| current.get(&change.key) != change.after.as_ref())
+        .map(describe)
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        return Ok(conflicts);
+    }
+
+    let Value::Object(mut value) = serde_json::to_value(guild)? else {
+        unreachable!("GuildData is always serialized as an object");
+    };
+    for change in &entry.changes {
+        match change.key.strip_prefix(TASK_PREFIX) {
+            Some(id) => {
+                let tasks = value["tasks"].as_array_mut().context("Tasks not found")?;
+                tasks.retain(|task| task["id"].as_str() != Some(id));
+                tasks.extend(change.before.clone());
+            }
+            None => match &change.before {
+                Some(before) => {
+                    value.insert(change.key.clone(), before.clone());
+                }
+                None => {
+                    value.remove(&change.key);
+                }
+            },
+        }
+    }
+    let reverted: GuildData = serde_json::from_value(Value::Object(value))?;
+    // 削除を取り消したタスクはゴミ箱から出す
+    reverted
+        .trash
+        .lock()
+        .unwrap()
+        .retain(|id, _| !reverted.tasks.lock().unwrap().contains_key(id));
+    guild.replace_with(reverted);
+    Ok(vec![])
+}
+
+/// 行を長さの上限までつなげ、入りきらなかった分は件数だけ書きます。
+pub fn truncate(lines: &[String], limit: usize) -> String {
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if text.chars().count() + line.chars().count() + 20 > limit {
+            text.push_str(&format!("…ほか{}件", lines.len() - i));
+            break;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+pub fn describe(change: &Change) -> String {
+    if change.key.starts_with(TASK_PREFIX) {
+        let task = |value: &Option<Value>| {
+            value
+                .clone()
+                .and_then(|value| serde_json::from_value::<Task>(value).ok())
+                .map(|task| format!("{} ({})", task.to_field().0, format_datetime(task.datetime)))
+        };
+        return match (task(&change.before), task(&change.after)) {
+            (None, Some(after)) => format!("➕ タスク: {}", after),
+            (Some(before), None) => format!("➖ タスク: {}", before),
+            (Some(before), Some(after)) if before != after => {
+                format!("✏️ タスク: {} → {}", before, after)
+            }
+            (Some(before), Some(_)) => {
+                format!("✏️ タスク: {} (繰り返しやリマインドの変更)", before)
+            }
+            (None, None) => format!("✏️ {}", change.key),
+        };
+    }
+
+    let label = match change.key.as_str() {
+        "subjects" => "教科",
+        "suggest_times" => "よく使う時間",
+        "panel_message" => "パネル",
+        "ping_channel" => "通知チャンネル",
+        "ping_role" => "通知ロール",
+        "log_channel" => "ログチャンネル",
+        "notify" => "通知の時刻と期間",
+        "reminder_days" => "リマインドの設定",
+        "admin_roles" => "管理者ロール",
+        "editor_roles" => "編集者ロール",
+        "timetable" => "時間割",
+        "school_calendar" => "年間行事予定",
+        key => key,
+    };
+    let value = |value: &Option<Value>| value.as_ref().map_or("なし".into(), Value::to_string);
+    format!(
+        "✏️ {}: {} → {}",
+        label,
+        value(&change.before),
+        value(&change.after)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::data::tests::task;
+
+    #[test]
+    fn diff_reports_added_removed_and_changed_keys() {
+        let before = Snapshot::from([
+            ("subjects".into(), json!(["国語"])),
+            ("tasks/a".into(), json!({"details": "a"})),
+            ("tasks/b".into(), json!({"details": "b"})),
+        ]);
+        let after = Snapshot::from([
+            ("subjects".into(), json!(["国語", "数学"])),
+            ("tasks/b".into(), json!({"details": "b"})),
+            ("tasks/c".into(), json!({"details": "c"})),
+        ]);
+        let changes = diff(&before, &after);
+        assert_eq!(
+            changes.iter().map(|c| c.key.as_str()).collect::<Vec<_>>(),
+            vec!["subjects", "tasks/a", "tasks/c"]
+        );
+        assert!(changes[1].after.is_none());
+        assert!(changes[2].before.is_none());
+    }
+
+    #[test]
+    fn snapshot_skips_bookkeeping_and_private_tasks() {
+        let guild = GuildData::default();
+        let private = Task {
+            owner: Some(UserId::new(1)),
+            ..task(2, "private")
+        };
+        for task in [task(1, "class"), private] {
+            guild.tasks.lock().unwrap().insert(task.id, task);
+        }
+        let snapshot = snapshot(&guild).unwrap();
+        assert!(snapshot.contains_key("tasks/00000000-0000-0000-0000-000000000001"));
+        assert!(!snapshot.contains_key("tasks/00000000-0000-0000-0000-000000000002"));
+        assert!(!snapshot.contains_key("last_notify"));
+        assert!(snapshot.contains_key("subjects"));
+    }
+
+    #[test]
+    fn revert_restores_previous_values_unless_changed_since() {
+        let guild = GuildData::default();
+        let before = snapshot(&guild).unwrap();
+        guild.subjects.lock().unwrap().insert("数学".into());
+        let after = snapshot(&guild).unwrap();
+        let entry = Entry {
+            at: Local::now(),
+            user: UserId::new(1),
+            action: "add_subjects".into(),
+            changes: diff(&before, &after),
+            undone: false,
+        };
+
+        assert!(revert(&guild, &entry).unwrap().is_empty());
+        assert!(guild.subjects.lock().unwrap().is_empty());
+        // 戻した後はもう変更後の状態ではないので、二重には戻さない
+        assert_eq!(revert(&guild, &entry).unwrap().len(), 1);
+    }
+}
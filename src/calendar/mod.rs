@@ -0,0 +1,318 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, Utc, Weekday};
+
+use crate::{
+    data::{Frequency, RecurrenceEnd, SchoolCalendar, RECURRENCE_HORIZON_DAYS},
+    Subject, Task,
+};
+
+mod server;
+pub use server::serve;
+
+/// タスクをiCalendar形式に変換します。
+/// UIDはタスクのIDから作るので、取り込み直しても予定は重複せずに更新されます。
+pub fn to_ics<'a>(
+    name: &str,
+    tasks: impl IntoIterator<Item = &'a Task>,
+    calendar: &SchoolCalendar,
+) -> String {
+    let stamp = Utc::now();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//task-bot-rs//JA".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+    for task in tasks {
+        lines.extend(events(task, stamp, calendar));
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+// 繰り返しタスクはRRULEを持つ1つの予定にし、個別に編集した回はRECURRENCE-IDで上書きする
+fn events(task: &Task, stamp: DateTime<Utc>, calendar: &SchoolCalendar) -> Vec<String> {
+    let Some(recurrence) = &task.recurrence else {
+        return event(task, stamp, vec![]);
+    };
+    let original = |date: NaiveDate| date.and_time(task.datetime.time());
+    let start = task.datetime.date_naive();
+
+    // 授業のある日だけ繰り返す場合、COUNTでは飛ばした日も数えられてしまうので、最後の回までとして書く
+    let end = match recurrence.end {
+        RecurrenceEnd::Count(_) if recurrence.school_days_only => recurrence
+            .dates(start, calendar)
+            .last()
+            .map_or(recurrence.end, RecurrenceEnd::Until),
+        end => end,
+    };
+
+    let mut rule = vec![
+        match recurrence.frequency {
+            Frequency::Daily => "FREQ=DAILY".to_string(),
+            Frequency::Weekly(_) => "FREQ=WEEKLY".to_string(),
+        },
+        format!("INTERVAL={}", recurrence.interval.max(1)),
+    ];
+    if let Frequency::Weekly(weekdays) = &recurrence.frequency {
+        if !weekdays.is_empty() {
+            let days = weekdays.iter().map(|&w| byday(w)).collect::<Vec<_>>();
+            rule.push(format!("BYDAY={}", days.join(",")));
+        }
+    }
+    match end {
+        RecurrenceEnd::Never => {}
+        RecurrenceEnd::Until(until) => rule.push(format!("UNTIL={}", local_time(original(until)))),
+        RecurrenceEnd::Count(count) => rule.push(format!("COUNT={}", count)),
+    }
+
+    let mut extra = vec![format!("RRULE:{}", rule.join(";"))];
+    // スキップした回だけEXDATEで消す。編集した回までEXDATEにすると、上書きした予定も消えてしまう
+    extra.extend(
+        recurrence
+            .exceptions
+            .iter()
+            .filter(|(_, exception)| exception.is_none())
+            .map(|(&date, _)| format!("EXDATE:{}", local_time(original(date)))),
+    );
+    // 授業の無い日の回も、配信する範囲の中ではEXDATEで消す
+    if recurrence.school_days_only {
+        let horizon =
+            (stamp.with_timezone(&Local) + Duration::days(RECURRENCE_HORIZON_DAYS)).date_naive();
+        let last = match end {
+            RecurrenceEnd::Until(until) => until.min(horizon),
+            _ => horizon,
+        };
+        extra.extend(
+            recurrence
+                .rule_dates(start)
+                .take_while(|date| *date <= last)
+                .filter(|date| {
+                    !calendar.is_school_day(*date) && !recurrence.exceptions.contains_key(date)
+                })
+                .map(|date| format!("EXDATE:{}", local_time(original(date)))),
+        );
+    }
+    let mut lines = event(task, stamp, extra);
+
+    for (&date, exception) in &recurrence.exceptions {
+        if let Some(exception) = exception {
+            lines.extend(event(
+                &Task {
+                    id: task.id,
+                    ..exception.clone()
+                },
+                stamp,
+                vec![format!("RECURRENCE-ID:{}", local_time(original(date)))],
+            ));
+        }
+    }
+    lines
+}
+
+fn event(task: &Task, stamp: DateTime<Utc>, extra: Vec<String>) -> Vec<String> {
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@task-bot-rs", task.id),
+        format!("DTSTAMP:{}", timestamp(stamp)),
+        format!("DTSTART:{}", local_time(task.datetime.naive_local())),
+        format!("DTEND:{}", local_time(task.datetime.naive_local())),
+        format!("SUMMARY:{}", escape(&summary(task))),
+        format!("DESCRIPTION:{}", escape(&task.details)),
+        format!("CATEGORIES:{}", escape(&task.category.to_string())),
+    ];
+    lines.extend(task.source.iter().map(|source| format!("URL:{}", source)));
+    lines.extend(extra);
+    if let Some(minutes) = task.remind_before {
+        lines.extend([
+            "BEGIN:VALARM".to_string(),
+            "ACTION:DISPLAY".to_string(),
+            format!("DESCRIPTION:{}", escape(&summary(task))),
+            format!("TRIGGER:-PT{}M", minutes),
+            "END:VALARM".to_string(),
+        ]);
+    }
+    lines.push("END:VEVENT".to_string());
+    lines
+}
+
+fn summary(task: &Task) -> String {
+    format!(
+        "【{}】{}{}",
+        task.category,
+        match &task.subject {
+            Subject::Set(s) => format!("{} ", s),
+            Subject::Unset => "".to_string(),
+        },
+        task.details
+    )
+}
+
+fn timestamp(datetime: DateTime<Utc>) -> String {
+    datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// BYDAYの曜日がずれないように、予定の日時はUTCにせずタイムゾーンの無い現地時刻で書く
+fn local_time(datetime: NaiveDateTime) -> String {
+    datetime.format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn byday(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// 1行は75バイトまでなので、文字の途中で切らないように折り返す
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Local, TimeZone};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        data::{DateRange, Recurrence},
+        Category,
+    };
+
+    fn task() -> Task {
+        Task {
+            id: Uuid::nil(),
+            category: Category::Homework,
+            subject: Subject::Set("数学".into()),
+            details: "問題集, p.10".into(),
+            datetime: Local.with_ymd_and_hms(2024, 4, 1, 9, 0, 0).unwrap(),
+            remind_before: Some(30),
+            recurrence: None,
+            owner: None,
+            source: None,
+            occurrence: None,
+        }
+    }
+
+    #[test]
+    fn maps_task_to_event() {
+        let ics = to_ics("タスク", [&task()], &SchoolCalendar::default());
+        assert!(ics.contains("UID:00000000-0000-0000-0000-000000000000@task-bot-rs\r\n"));
+        assert!(ics.contains("SUMMARY:【宿題】数学 問題集\\, p.10\r\n"));
+        assert!(ics.contains("CATEGORIES:宿題\r\n"));
+        assert!(ics.contains("TRIGGER:-PT30M\r\n"));
+    }
+
+    #[test]
+    fn maps_recurrence_to_rrule() {
+        let date = |d| NaiveDate::from_ymd_opt(2024, 4, d).unwrap();
+        let edited = Task {
+            details: "問題集, p.20".into(),
+            datetime: Local.with_ymd_and_hms(2024, 4, 4, 10, 0, 0).unwrap(),
+            ..task()
+        };
+        let task = Task {
+            recurrence: Some(Recurrence {
+                frequency: Frequency::Weekly(vec![Weekday::Mon, Weekday::Wed]),
+                interval: 2,
+                end: RecurrenceEnd::Count(6),
+                exceptions: BTreeMap::from([(date(1), None), (date(3), Some(edited))]),
+                school_days_only: false,
+            }),
+            ..task()
+        };
+        let ics = to_ics("タスク", [&task], &SchoolCalendar::default());
+        assert!(ics.contains("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=6\r\n"));
+        // スキップした回はEXDATE、編集した回はRECURRENCE-IDだけで表す
+        assert!(ics.contains("EXDATE:20240401T090000\r\n"));
+        assert_eq!(ics.matches("EXDATE:").count(), 1);
+        assert!(ics.contains("RECURRENCE-ID:20240403T090000\r\n"));
+        assert!(ics.contains("DTSTART:20240404T100000\r\n"));
+    }
+
+    #[test]
+    fn excludes_non_school_days() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let calendar = SchoolCalendar {
+            holidays: vec![DateRange {
+                name: "昭和の日".into(),
+                start: date(4, 29),
+                end: date(4, 29),
+            }],
+            ..Default::default()
+        };
+        let task = Task {
+            datetime: Local.with_ymd_and_hms(2024, 4, 22, 9, 0, 0).unwrap(),
+            recurrence: Some(Recurrence {
+                frequency: Frequency::Weekly(vec![Weekday::Mon]),
+                interval: 1,
+                end: RecurrenceEnd::Count(3),
+                exceptions: BTreeMap::new(),
+                school_days_only: true,
+            }),
+            ..task()
+        };
+        let ics = to_ics("タスク", [&task], &calendar);
+        // 4/29を飛ばして3回目は5/13になる
+        assert!(ics.contains("RRULE:FREQ=WEEKLY;INTERVAL=1;BYDAY=MO;UNTIL=20240513T090000\r\n"));
+        assert!(ics.contains("EXDATE:20240429T090000\r\n"));
+        assert_eq!(ics.matches("EXDATE:").count(), 1);
+    }
+
+    #[test]
+    fn early_morning_weekly_task_keeps_its_weekday() {
+        // UTCに直すと前の日の日曜日になる時刻
+        let task = Task {
+            datetime: Local.with_ymd_and_hms(2024, 4, 1, 0, 30, 0).unwrap(),
+            recurrence: Some(Recurrence {
+                frequency: Frequency::Weekly(vec![Weekday::Mon]),
+                interval: 1,
+                end: RecurrenceEnd::Never,
+                exceptions: BTreeMap::new(),
+                school_days_only: false,
+            }),
+            ..task()
+        };
+        let ics = to_ics("タスク", [&task], &SchoolCalendar::default());
+        assert!(ics.contains("DTSTART:20240401T003000\r\n"));
+        assert!(ics.contains("RRULE:FREQ=WEEKLY;INTERVAL=1;BYDAY=MO\r\n"));
+    }
+
+    #[test]
+    fn folds_long_lines_without_splitting_characters() {
+        let folded = fold(&format!("SUMMARY:{}", "あ".repeat(40)));
+        for line in folded.split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert_eq!(
+            folded.replace("\r\n ", ""),
+            format!("SUMMARY:{}\r\n", "あ".repeat(40))
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{num::NonZeroU64, sync::Arc, time::Duration};
+
+use anyhow::{Context as _, Error};
+use poise::serenity_prelude::*;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::Data;
+
+/// リクエスト行とヘッダーの合計の最大のバイト数
+const MAX_HEADER_BYTES: u64 = 8192;
+/// リクエストを読み終えるまで待つ時間
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// カレンダーアプリから購読できるように、`/calendar/<サーバーID>/<トークン>.ics`でタスクを配信します。
+pub async fn serve(data: Arc<Data>, addr: String) -> Result<(), Error> {
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Calendar feed listening on {}", addr);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let data = data.clone();
+        tokio::spawn(async move {
+            if let Err(error) = respond(stream, &data).await {
+                println!("Failed to serve calendar: {:#}", error);
+            }
+        });
+    }
+}
+
+async fn respond(stream: TcpStream, data: &Data) -> Result<(), Error> {
+    let mut stream = BufReader::new(stream.take(MAX_HEADER_BYTES));
+    let request_line = timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .context("Timed out reading request")??;
+
+    let response = match request_line
+        .as_deref()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+    {
+        Some(words) => match words[..] {
+            ["GET", path, _] => feed(data, path).ok_or("404 Not Found"),
+            _ => Err("400 Bad Request"),
+        },
+        None => Err("400 Bad Request"),
+    };
+    let (status, content_type, body) = match response {
+        Ok(body) => ("200 OK", "text/calendar; charset=utf-8", body),
+        Err(status) => (status, "text/plain; charset=utf-8", status.into()),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let stream = stream.get_mut().get_mut();
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// リクエスト行を返します。ヘッダーは使わないが、読み切ってから返す。
+// 上限のバイト数を超えたり途中で切れたりして、ヘッダーの終わりまで読めなければNoneを返す
+async fn read_request<R: AsyncBufReadExt + Unpin>(stream: &mut R) -> Result<Option<String>, Error> {
+    let mut request_line = String::new();
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if !line.ends_with('\n') {
+            return Ok(None);
+        }
+        if request_line.is_empty() {
+            request_line = line;
+        } else if line.trim().is_empty() {
+            return Ok(Some(request_line));
+        }
+    }
+}
+
+// トークンが一致したときだけ、クラス全体のタスクを返す
+fn feed(data: &Data, path: &str) -> Option<String> {
+    let path = path.split('?').next()?.strip_prefix("/calendar/")?;
+    let (guild_id, token) = path.split_once('/')?;
+    let token = token.strip_suffix(".ics")?;
+    let guild_id = GuildId::from(guild_id.parse::<NonZeroU64>().ok()?);
+    let guild = data.guilds.lock().unwrap().get(&guild_id)?.clone();
+    if guild.calendar_token.lock().unwrap().as_deref() != Some(token) {
+        return None;
+    }
+    let calendar = guild.school_calendar.lock().unwrap().clone();
+    let tasks = guild.tasks.lock().unwrap();
+    Some(super::to_ics(
+        "タスク",
+        tasks.values().filter(|task| task.owner.is_none()),
+        &calendar,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_oversized_headers() {
+        let request = b"GET /calendar/1/a.ics HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert_eq!(
+            read_request(&mut &request[..]).await.unwrap().as_deref(),
+            Some("GET /calendar/1/a.ics HTTP/1.1\r\n")
+        );
+
+        let header = format!("X-Padding: {}\r\n", "a".repeat(MAX_HEADER_BYTES as usize));
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", header);
+        let mut stream = BufReader::new(request.as_bytes().take(MAX_HEADER_BYTES));
+        assert_eq!(read_request(&mut stream).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use futures::StreamExt;
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit::truncate, commands::permission::is_admin, data, utilities::format_datetime, PoiseContext,
+};
+
+const ENTRIES_PER_PAGE: usize = 5;
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// データの変更履歴を表示します。
+pub async fn audit_log(
+    ctx: PoiseContext<'_>,
+    #[description = "このメンバーの変更だけを表示する"] user: Option<User>,
+) -> Result<(), Error> {
+    const PREV: &str = "prev";
+    const NEXT: &str = "next";
+
+    let guild = data::guild(ctx)?;
+    let entries = guild
+        .audit_log
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .filter(|entry| user.as_ref().is_none_or(|user| entry.user == user.id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut page = 0;
+    let message = |page: usize| {
+        let fields = entries
+            .iter()
+            .skip(ENTRIES_PER_PAGE * page)
+            .take(ENTRIES_PER_PAGE)
+            .map(|entry| {
+                (
+                    format!("{} {}", format_datetime(entry.at), entry.action),
+                    format!(
+                        "{}\n{}",
+                        entry.user.mention(),
+                        truncate(&entry.describe(), 900)
+                    ),
+                    false,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("変更履歴")
+                    .description(if entries.is_empty() {
+                        "ありません"
+                    } else {
+                        ""
+                    })
+                    .fields(fields)
+                    .color(Color::DARK_BLUE),
+            )
+            .components(vec![CreateActionRow::Buttons(vec![
+                CreateButton::new(PREV)
+                    .label("前のページ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0),
+                CreateButton::new(NEXT)
+                    .label("次のページ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(entries.len() <= ENTRIES_PER_PAGE * (page + 1)),
+            ])])
+            .ephemeral(true)
+    };
+
+    let reply = ctx.send(message(page)).await?;
+    let mut interaction_stream = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .timeout(Duration::from_secs(60 * 30))
+        .stream();
+
+    while let Some(interaction) = interaction_stream.next().await {
+        match interaction.data.custom_id.as_str() {
+            PREV => page = page.saturating_sub(1),
+            NEXT => page += 1,
+            _ => continue,
+        }
+        interaction
+            .create_response(ctx, CreateInteractionResponse::Acknowledge)
+            .await?;
+        reply.edit(ctx, message(page)).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context as _, Error};
+use chrono::Duration;
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_admin,
+    data::{self, GuildData},
+    PoiseContext,
+};
+
+// 添付ファイルか、Botが送ったバックアップのメッセージへのリンクからファイルを取り出す
+async fn download(
+    ctx: PoiseContext<'_>,
+    file: Option<Attachment>,
+    message_link: Option<String>,
+) -> Result<Vec<u8>, Error> {
+    if let Some(file) = file {
+        return Ok(file.download().await?);
+    }
+    let message_link = message_link.context("No backup file or message link given")?;
+    let (guild_id, channel_id, message_id) =
+        utils::parse_message_url(message_link.trim()).context("Invalid message link")?;
+    ensure!(
+        Some(guild_id) == ctx.guild_id(),
+        "Message is not in this guild"
+    );
+    let message = channel_id.message(ctx, message_id).await?;
+    ensure!(
+        message.author.id == ctx.framework().bot_id,
+        "Message was not sent by this bot"
+    );
+    let attachment = message
+        .attachments
+        .iter()
+        .find(|a| a.filename.ends_with(".json"))
+        .context("Message has no backup file")?;
+    Ok(attachment.download().await?)
+}
+
+// バックアップにはそのサーバーのデータだけが入っているはずだが、サーバーごとに分ける前の形式も受け付ける
+fn parse(bytes: &[u8], guild_id: GuildId) -> Result<GuildData, Error> {
+    let value = serde_json::from_slice(bytes).context("Backup is not valid JSON")?;
+    let data = data::from_value(value).context("Failed to parse backup")?;
+    let mut guilds = data.guilds.into_inner().unwrap();
+    let legacy = data.legacy.into_inner().unwrap();
+
+    let guild = match guilds.remove(&guild_id) {
+        Some(guild) => guild,
+        None if guilds.is_empty() => legacy.context("Backup contains no data")?,
+        None => bail!("Backup belongs to another guild"),
+    };
+    // 読み込んだばかりなので、他から参照されていることはない
+    let guild = Arc::try_unwrap(guild)
+        .ok()
+        .context("Backup is still in use")?;
+    Ok(guild)
+}
+
+fn copy(guild: &GuildData) -> Result<GuildData, Error> {
+    Ok(serde_json::from_value(serde_json::to_value(guild)?)?)
+}
+
+// 今のデータを元に、バックアップにしか無いタスク・教科・よく使う時間を加える
+fn merge(current: &GuildData, backup: &GuildData) -> Result<GuildData, Error> {
+    let merged = copy(current)?;
+    {
+        let mut tasks = merged.tasks.lock().unwrap();
+        for (id, task) in backup.tasks.lock().unwrap().iter() {
+            tasks.entry(*id).or_insert_with(|| task.clone());
+        }
+        merged
+            .trash
+            .lock()
+            .unwrap()
+            .retain(|id, _| !tasks.contains_key(id));
+    }
+    merged
+        .subjects
+        .lock()
+        .unwrap()
+        .extend(backup.subjects.lock().unwrap().iter().cloned());
+    {
+        let mut suggest_times = merged.suggest_times.lock().unwrap();
+        for (time, label) in backup.suggest_times.lock().unwrap().iter() {
+            suggest_times.entry(*time).or_insert_with(|| label.clone());
+        }
+    }
+    Ok(merged)
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// バックアップからデータを復元します。
+pub async fn restore_backup(
+    ctx: PoiseContext<'_>,
+    #[description = "バックアップのファイル"] file: Option<Attachment>,
+    #[description = "バックアップが送られたメッセージのリンク"] message_link: Option<String>,
+) -> Result<(), Error> {
+    const REPLACE: &str = "replace";
+    const MERGE: &str = "merge";
+    const CANCEL: &str = "cancel";
+
+    let guild = data::guild(ctx)?;
+    let guild_id = ctx.guild_id().context("Not in a guild")?;
+    ctx.defer().await?;
+
+    let backup = parse(&download(ctx, file, message_link).await?, guild_id)?;
+    let merged = merge(&guild, &backup)?;
+    {
+        // 定期バックアップには個人のタスクが入っていないので、今のものを残す
+        let mut tasks = backup.tasks.lock().unwrap();
+        let mut trash = backup.trash.lock().unwrap();
+        for (id, task) in guild.tasks.lock().unwrap().iter() {
+            if task.owner.is_some() {
+                tasks.insert(*id, task.clone());
+            }
+        }
+        for (id, trashed) in guild.trash.lock().unwrap().iter() {
+            if trashed.task.owner.is_some() {
+                trash.insert(*id, trashed.clone());
+            }
+        }
+        trash.retain(|id, _| !tasks.contains_key(id));
+    }
+    // メンバーごとの購読や完了の記録も定期バックアップには入っていない
+    backup
+        .subscriptions
+        .lock()
+        .unwrap()
+        .extend(guild.subscriptions.lock().unwrap().clone());
+    {
+        let mut completed = backup.completed.lock().unwrap();
+        for (user, done) in guild.completed.lock().unwrap().iter() {
+            completed
+                .entry(*user)
+                .or_default()
+                .extend(done.iter().cloned());
+        }
+    }
+
+    let current = audit::snapshot(&guild)?;
+    let summary = |other: &GuildData| -> Result<String, Error> {
+        let changes = audit::diff(&current, &audit::snapshot(other)?);
+        Ok(if changes.is_empty() {
+            "変更はありません".to_string()
+        } else {
+            audit::truncate(
+                &changes.iter().map(audit::describe).collect::<Vec<_>>(),
+                1000,
+            )
+        })
+    };
+
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("バックアップから復元します")
+                        .field("置き換える場合", summary(&backup)?, false)
+                        .field("統合する場合", summary(&merged)?, false)
+                        .color(Color::DARK_BLUE),
+                )
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(REPLACE)
+                        .label("置き換える")
+                        .style(ButtonStyle::Danger),
+                    CreateButton::new(MERGE)
+                        .label("統合する")
+                        .style(ButtonStyle::Primary),
+                    CreateButton::new(CANCEL)
+                        .label("キャンセル")
+                        .style(ButtonStyle::Secondary),
+                ])]),
+        )
+        .await?;
+
+    let interaction = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .timeout(Duration::seconds(60 * 30).to_std()?)
+        .await
+        .context("No interaction")?;
+
+    let title = match interaction.data.custom_id.as_str() {
+        REPLACE => {
+            guild.replace_with(backup);
+            audit::save(ctx).await?;
+            "バックアップで置き換えました"
+        }
+        MERGE => {
+            guild.replace_with(merged);
+            audit::save(ctx).await?;
+            "バックアップを統合しました"
+        }
+        _ => "復元をキャンセルしました",
+    };
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(CreateEmbed::default().title(title).color(Color::DARK_GREEN))
+            .components(vec![]),
+    );
+    interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{data::tests::task, Data};
+
+    fn guild(tasks: &[(u128, &str)], subjects: &[&str]) -> GuildData {
+        let guild = GuildData::default();
+        for &(id, details) in tasks {
+            let task = task(id, details);
+            guild.tasks.lock().unwrap().insert(task.id, task);
+        }
+        *guild.subjects.lock().unwrap() = subjects.iter().map(|s| s.to_string()).collect();
+        guild
+    }
+
+    #[test]
+    fn merge_adds_only_missing_items() {
+        let current = guild(&[(1, "current")], &["国語"]);
+        let backup = guild(&[(1, "old"), (2, "lost")], &["数学"]);
+
+        let merged = merge(&current, &backup).unwrap();
+        let tasks = merged.tasks.lock().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[&Uuid::from_u128(1)].details, "current");
+        assert_eq!(merged.subjects.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn restore_keeps_scheduler_state() {
+        let current = guild(&[(1, "current")], &[]);
+        *current.panel_message.lock().unwrap() = Some((MessageId::new(1), ChannelId::new(2)));
+        *current.last_notify.lock().unwrap() = Some(Local::now());
+        *current.last_backup.lock().unwrap() = Some(Local::now());
+        current.sent_reminders.lock().unwrap().insert((
+            Uuid::from_u128(1),
+            Local::now().date_naive(),
+            1,
+        ));
+        *current.calendar_token.lock().unwrap() = Some("current-token".into());
+        let backup = guild(&[(1, "backup")], &[]);
+        *backup.calendar_token.lock().unwrap() = Some("backup-token".into());
+
+        current.replace_with(backup);
+        let tasks = current.tasks.lock().unwrap();
+        assert!(tasks.values().all(|task| task.details == "backup"));
+        assert!(current.last_notify.lock().unwrap().is_some());
+        assert!(current.last_backup.lock().unwrap().is_some());
+        assert!(current.panel_message.lock().unwrap().is_some());
+        assert_eq!(current.sent_reminders.lock().unwrap().len(), 1);
+        assert_eq!(
+            current.calendar_token.lock().unwrap().as_deref(),
+            Some("current-token")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_backup_of_another_guild() {
+        let data = Data::default();
+        data.guilds
+            .lock()
+            .unwrap()
+            .insert(GuildId::new(1), Arc::new(GuildData::default()));
+        let backup = data::to_value(&data).unwrap().to_string();
+        assert!(parse(backup.as_bytes(), GuildId::new(1)).is_ok());
+        assert!(parse(backup.as_bytes(), GuildId::new(2)).is_err());
+    }
+}
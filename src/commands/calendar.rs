@@ -0,0 +1,92 @@
+use anyhow::{Context as _, Error};
+use poise::serenity_prelude::*;
+use uuid::Uuid;
+
+use crate::{audit, calendar, commands::permission::is_admin, data, PoiseContext};
+
+// 配信URLは`CALENDAR_URL`が設定されているときだけ案内する
+fn feed_url(guild_id: GuildId, token: &str) -> Option<String> {
+    let base = std::env::var("CALENDAR_URL").ok()?;
+    Some(format!(
+        "{}/calendar/{}/{}.ics",
+        base.trim_end_matches('/'),
+        guild_id,
+        token
+    ))
+}
+
+#[poise::command(slash_command, guild_only)]
+/// タスクをカレンダーのファイルとして書き出します。
+pub async fn export_calendar(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let guild_id = ctx.guild_id().context("Not in a guild")?;
+    let ics = {
+        let tasks = guild.tasks.lock().unwrap();
+        calendar::to_ics(
+            "タスク",
+            tasks
+                .values()
+                .filter(|task| task.visible_to(ctx.author().id)),
+            &guild.school_calendar.lock().unwrap(),
+        )
+    };
+
+    let token = guild.calendar_token.lock().unwrap().clone();
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let token = Uuid::new_v4().simple().to_string();
+            guild.calendar_token.lock().unwrap().replace(token.clone());
+            audit::save(ctx).await?;
+            token
+        }
+    };
+    let description = match feed_url(guild_id, &token) {
+        Some(url) => format!(
+            "ファイルを読み込むか、次のURLを購読してください(個人のタスクは含まれません)\n{}",
+            url
+        ),
+        None => "ファイルをカレンダーアプリで読み込んでください".to_string(),
+    };
+
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("カレンダーに書き出しました")
+                    .description(description)
+                    .color(Color::DARK_BLUE),
+            )
+            .attachment(CreateAttachment::bytes(ics, "tasks.ics"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// カレンダーの配信URLを変更し、今までのURLを使えなくします。
+pub async fn reset_calendar_feed(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    guild
+        .calendar_token
+        .lock()
+        .unwrap()
+        .replace(Uuid::new_v4().simple().to_string());
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("カレンダーの配信URLを変更しました")
+                    .description("新しいURLは/export_calendarで確認できます")
+                    .color(Color::DARK_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context as _, Error};
+use poise::serenity_prelude::*;
+
+use crate::{data, Data, Task};
+
+// リマインドのメッセージはBotが再起動しても押せるように、イベントハンドラーで受け取る
+const DONE: &str = "done";
+
+/// 1つのタスクを完了にするボタン
+pub fn done_button(task: &Task) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new(format!("{}:{}", DONE, task.key()))
+        .label("完了にする")
+        .style(ButtonStyle::Success)])
+}
+
+/// 複数のタスクから完了にするものを選ぶメニュー
+pub fn done_menu(tasks: &[&Task]) -> CreateActionRow {
+    let options = tasks
+        .iter()
+        .take(25)
+        .map(|task| CreateSelectMenuOption::new(task.to_field().0, task.key()))
+        .collect();
+    CreateActionRow::SelectMenu(
+        CreateSelectMenu::new(DONE, CreateSelectMenuKind::String { options })
+            .placeholder("完了したタスクを選択"),
+    )
+}
+
+pub async fn handle_done_interaction(
+    ctx: &Context,
+    data: &Data,
+    interaction: &ComponentInteraction,
+) -> Result<(), Error> {
+    let key = match &interaction.data.kind {
+        ComponentInteractionDataKind::Button => match interaction.data.custom_id.split_once(':') {
+            Some((DONE, key)) => key,
+            _ => return Ok(()),
+        },
+        ComponentInteractionDataKind::StringSelect { values }
+            if interaction.data.custom_id == DONE =>
+        {
+            &values[0]
+        }
+        _ => return Ok(()),
+    };
+    let guild = data.guild(interaction.guild_id.context("Not in a guild")?);
+
+    let (title, task) = match guild.find_task(key) {
+        Some(task) => {
+            let title = if guild.toggle_done(interaction.user.id, &task) {
+                "完了にしました"
+            } else {
+                "未完了に戻しました"
+            };
+            data::save(data)?;
+            (title, Some(task))
+        }
+        None => ("タスクが見つかりません", None),
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::default()
+                    .embed(
+                        CreateEmbed::default()
+                            .title(title)
+                            .fields(task.iter().map(Task::to_field))
+                            .color(Color::DARK_GREEN),
+                    )
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context as _, Error};
+use chrono::{Duration, Local, NaiveTime, TimeZone};
+use poise::serenity_prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, truncate},
+    commands::permission::is_editor,
+    data,
+    utilities::parse_date,
+    Category, PoiseContext, Subject, Task,
+};
+
+/// 1つのファイルから取り込めるタスクの数
+const IMPORT_LIMIT: usize = 500;
+/// 確認画面に表示するタスクの数
+const PREVIEW_LIMIT: usize = 10;
+// Excelで開いても文字化けしないように、CSVの先頭に付ける
+const BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum FileFormat {
+    #[name = "CSV"]
+    Csv,
+    #[name = "JSON"]
+    Json,
+}
+
+/// ファイルの1行分。CSVでは1行目に列名を書きます。
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Row {
+    category: String,
+    subject: Option<String>,
+    date: String,
+    time: Option<String>,
+    #[serde(default)]
+    details: String,
+}
+
+impl From<&Task> for Row {
+    fn from(task: &Task) -> Self {
+        Row {
+            category: task.category.to_string(),
+            subject: match &task.subject {
+                Subject::Set(s) => Some(s.clone()),
+                Subject::Unset => None,
+            },
+            date: task.datetime.format("%Y-%m-%d").to_string(),
+            time: Some(task.datetime.format("%H:%M").to_string()),
+            details: task.details.clone(),
+        }
+    }
+}
+
+fn read_rows(bytes: &[u8], format: FileFormat) -> Result<Vec<Row>, Error> {
+    let text = std::str::from_utf8(bytes).context("File is not UTF-8")?;
+    let text = text.strip_prefix(BOM).unwrap_or(text);
+    match format {
+        FileFormat::Csv => csv::Reader::from_reader(text.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .context("Failed to parse CSV"),
+        FileFormat::Json => serde_json::from_str(text).context("Failed to parse JSON"),
+    }
+}
+
+fn write_rows(rows: &[Row], format: FileFormat) -> Result<String, Error> {
+    match format {
+        FileFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(BOM.as_bytes().to_vec());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        FileFormat::Json => Ok(serde_json::to_string_pretty(rows)?),
+    }
+}
+
+fn to_task(row: &Row, subjects: &BTreeSet<String>) -> Result<Task, String> {
+    let category = Category::from_name(row.category.trim())
+        .ok_or_else(|| format!("カテゴリー「{}」はありません", row.category))?;
+    let subject = match row.subject.as_deref().map(str::trim) {
+        None | Some("") => Subject::Unset,
+        Some(s) if subjects.contains(s) => Subject::Set(s.to_string()),
+        Some(s) => return Err(format!("教科「{}」は登録されていません", s)),
+    };
+    let date =
+        parse_date(&row.date).ok_or_else(|| format!("日付「{}」が読み取れません", row.date))?;
+    let time = row.time.as_deref().map(str::trim).unwrap_or_default();
+    let time = NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("時刻「{}」が読み取れません", time))?;
+    let datetime = Local
+        .from_local_datetime(&date.and_time(time))
+        .single()
+        .ok_or_else(|| format!("{} {}は存在しない時刻です", date, time))?;
+    if row.details.trim().is_empty() {
+        return Err("詳細がありません".to_string());
+    }
+
+    Ok(Task {
+        id: Uuid::new_v4(),
+        category,
+        subject,
+        details: row.details.trim().to_string(),
+        datetime,
+        remind_before: None,
+        recurrence: None,
+        owner: None,
+        source: None,
+        occurrence: None,
+    })
+}
+
+/// すべての行を確かめ、1つでも問題があれば取り込まずに行ごとの問題を返します。
+fn to_tasks(rows: &[Row], subjects: &BTreeSet<String>) -> Result<Vec<Task>, Vec<String>> {
+    let (tasks, errors): (Vec<_>, Vec<_>) = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| to_task(row, subjects).map_err(|e| format!("{}件目: {}", i + 1, e)))
+        .partition(Result::is_ok);
+    if errors.is_empty() {
+        Ok(tasks.into_iter().map(Result::unwrap).collect())
+    } else {
+        Err(errors.into_iter().map(Result::unwrap_err).collect())
+    }
+}
+
+#[poise::command(slash_command, guild_only)]
+/// CSVかJSONのファイルからタスクをまとめて追加します。
+pub async fn import_tasks(
+    ctx: PoiseContext<'_>,
+    #[description = "category, subject, date, time, detailsの列を持つCSVかJSONのファイル"]
+    file: Attachment,
+    #[description = "取り込んだタスクを自分だけの個人のタスクにするか"] private: Option<bool>,
+) -> Result<(), Error> {
+    const IMPORT: &str = "import";
+    const CANCEL: &str = "cancel";
+
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
+    ctx.defer_ephemeral().await?;
+
+    let format = if file.filename.to_lowercase().ends_with(".json") {
+        FileFormat::Json
+    } else {
+        FileFormat::Csv
+    };
+    let rows = read_rows(&file.download().await?, format)?;
+    let subjects = guild.subjects.lock().unwrap().clone();
+    let result = if rows.is_empty() {
+        Err(vec!["タスクがありません".to_string()])
+    } else if rows.len() > IMPORT_LIMIT {
+        Err(vec![format!(
+            "一度に取り込めるのは{}件までです",
+            IMPORT_LIMIT
+        )])
+    } else {
+        to_tasks(&rows, &subjects)
+    };
+    let mut tasks = match result {
+        Ok(tasks) => tasks,
+        Err(errors) => {
+            ctx.send(
+                poise::CreateReply::default()
+                    .embed(
+                        CreateEmbed::default()
+                            .title("ファイルに問題があるため取り込めません")
+                            .description(truncate(&errors, 4000))
+                            .color(Color::DARK_RED),
+                    )
+                    .ephemeral(true),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    for task in &mut tasks {
+        task.owner = owner;
+    }
+    tasks.sort_by_key(|task| task.datetime);
+
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title(format!("{}件のタスクを追加します", tasks.len()))
+                        .description(if tasks.len() > PREVIEW_LIMIT {
+                            format!("…ほか{}件", tasks.len() - PREVIEW_LIMIT)
+                        } else {
+                            "".to_string()
+                        })
+                        .fields(tasks.iter().take(PREVIEW_LIMIT).map(Task::to_field))
+                        .color(Color::DARK_BLUE),
+                )
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(IMPORT)
+                        .label("追加する")
+                        .style(ButtonStyle::Primary),
+                    CreateButton::new(CANCEL)
+                        .label("キャンセル")
+                        .style(ButtonStyle::Secondary),
+                ])])
+                .ephemeral(true),
+        )
+        .await?;
+
+    let interaction = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .timeout(Duration::seconds(60 * 30).to_std()?)
+        .await
+        .context("No interaction")?;
+
+    let title = if interaction.data.custom_id == IMPORT {
+        guild
+            .tasks
+            .lock()
+            .unwrap()
+            .extend(tasks.iter().map(|task| (task.id, task.clone())));
+        audit::save(ctx).await?;
+        format!("{}件のタスクを追加しました", tasks.len())
+    } else {
+        "追加をキャンセルしました".to_string()
+    };
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(CreateEmbed::default().title(title).color(Color::DARK_GREEN))
+            .components(vec![]),
+    );
+    interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only)]
+/// タスクをCSVかJSONのファイルに書き出します。
+pub async fn export_tasks(
+    ctx: PoiseContext<'_>,
+    #[description = "ファイルの形式"] format: FileFormat,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let rows = {
+        let tasks = guild.tasks.lock().unwrap();
+        let mut tasks = tasks
+            .values()
+            .filter(|task| task.visible_to(ctx.author().id))
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| task.datetime);
+        tasks.into_iter().map(Row::from).collect::<Vec<_>>()
+    };
+    let extension = match format {
+        FileFormat::Csv => "csv",
+        FileFormat::Json => "json",
+    };
+
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title(format!("{}件のタスクを書き出しました", rows.len()))
+                    .description("繰り返しタスクは最初の回だけが含まれます")
+                    .color(Color::DARK_BLUE),
+            )
+            .attachment(CreateAttachment::bytes(
+                write_rows(&rows, format)?,
+                format!("tasks.{}", extension),
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_csv_and_reports_every_invalid_row() {
+        let csv = "\u{feff}category,subject,date,time,details\n\
+                   テスト,数学,2024-07-01,09:00,期末テスト\n\
+                   Homework,,2024/07/02,8:30,ワーク\n\
+                   宿題,美術,2024-07-03,09:00,スケッチ\n\
+                   遊び,数学,2024-07-32,25:00,\n";
+        let rows = read_rows(csv.as_bytes(), FileFormat::Csv).unwrap();
+        let subjects = BTreeSet::from(["数学".to_string()]);
+
+        let errors = to_tasks(&rows, &subjects).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("3件目"));
+
+        let tasks = to_tasks(&rows[..2], &subjects).unwrap();
+        assert_eq!(tasks[0].category, Category::Exam);
+        assert_eq!(tasks[1].subject, Subject::Unset);
+    }
+
+    #[test]
+    fn exported_rows_can_be_imported_again() {
+        let rows = vec![Row {
+            category: "持ち物".into(),
+            subject: None,
+            date: "2024-07-01".into(),
+            time: Some("08:30".into()),
+            details: "体操服, 水筒".into(),
+        }];
+        for format in [FileFormat::Csv, FileFormat::Json] {
+            let written = write_rows(&rows, format).unwrap();
+            assert_eq!(read_rows(written.as_bytes(), format).unwrap(), rows);
+        }
+        let task = to_task(&rows[0], &BTreeSet::new()).unwrap();
+        assert_eq!(Row::from(&task), rows[0]);
+    }
+}
@@ -2,17 +2,14 @@ use anyhow::Error;
 
 use poise::serenity_prelude::*;
 
-use crate::{data, PoiseContext};
+use crate::{audit, commands::permission::is_admin, data, PoiseContext};
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_admin")]
 /// 管理者向けログを送るチャンネルを設定します。
 pub async fn set_log_channel(ctx: PoiseContext<'_>) -> Result<(), Error> {
-    ctx.data()
-        .log_channel
-        .lock()
-        .unwrap()
-        .replace(ctx.channel_id());
-    data::save(ctx.data())?;
+    let guild = data::guild(ctx)?;
+    guild.log_channel.lock().unwrap().replace(ctx.channel_id());
+    audit::save(ctx).await?;
 
     ctx.send(
         poise::CreateReply::default().embed(
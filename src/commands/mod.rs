@@ -1,6 +1,18 @@
+pub mod audit_log;
+pub mod backup;
+pub mod calendar;
+pub mod done;
+pub mod import_export;
 pub mod log_config;
 pub mod modify_subjects;
 pub mod modify_suggest_times;
 pub mod modify_tasks;
 pub mod panel;
+pub mod permission;
 pub mod ping_config;
+pub mod quick_add;
+pub mod reminder_config;
+pub mod school_calendar;
+pub mod subscription;
+pub mod timetable;
+pub mod trash;
@@ -4,29 +4,30 @@ use anyhow::{Context as _, Error};
 use futures::StreamExt;
 use poise::serenity_prelude::*;
 
-use crate::{data, PoiseContext};
+use crate::{audit, commands::permission::is_editor, data, PoiseContext};
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_editor")]
 /// 教科を追加します。
 pub async fn add_subjects(
     ctx: PoiseContext<'_>,
     #[description = "追加したい教科 / カンマ区切りで複数追加できます"] subjects: String,
 ) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
     let subjects = subjects
         .split(',')
         .map(|s| s.trim().to_string())
         .collect::<Vec<_>>();
 
-    ctx.data()
+    guild
         .subjects
         .lock()
         .unwrap()
         .extend(subjects.clone().into_iter());
-    data::save(ctx.data())?;
+    audit::save(ctx).await?;
 
     let diff = format!(
         "```diff\n{}\n```",
-        ctx.data()
+        guild
             .subjects
             .lock()
             .unwrap()
@@ -49,13 +50,15 @@ pub async fn add_subjects(
     Ok(())
 }
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_editor")]
 /// 教科を削除します。
+#[allow(clippy::collapsible_match)]
 pub async fn remove_subject(ctx: PoiseContext<'_>) -> Result<(), Error> {
     const SUBJECT: &str = "subject";
     const SUBMIT: &str = "submit";
 
-    let subjects = ctx.data().subjects.lock().unwrap().clone();
+    let guild = data::guild(ctx)?;
+    let subjects = guild.subjects.lock().unwrap().clone();
 
     let components = |selected_subject: Option<String>| {
         let subject_options = CreateSelectMenuKind::String {
@@ -127,7 +130,7 @@ pub async fn remove_subject(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let subject = select.context("Subject not selected")?;
     let diff = format!(
         "```diff\n{}\n```",
-        ctx.data()
+        guild
             .subjects
             .lock()
             .unwrap()
@@ -137,12 +140,8 @@ pub async fn remove_subject(ctx: PoiseContext<'_>) -> Result<(), Error> {
             .join("\n")
     );
 
-    ctx.data()
-        .subjects
-        .lock()
-        .unwrap()
-        .retain(|s| s != &subject);
-    data::save(ctx.data())?;
+    guild.subjects.lock().unwrap().retain(|s| s != &subject);
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
@@ -6,14 +6,17 @@ use chrono::NaiveTime;
 use futures::StreamExt;
 use poise::serenity_prelude::*;
 
-use crate::{data, interactions::select_time, PoiseContext};
+use crate::{
+    audit, commands::permission::is_editor, data, interactions::select_time, PoiseContext,
+};
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_editor")]
 /// よく使う時間を追加します。
 pub async fn add_suggest_time(
     ctx: PoiseContext<'_>,
     #[description = "よく使う時間のラベル(例: 1限開始時刻)"] label: String,
 ) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
     let (interaction, time) = select_time(
         ctx,
         None,
@@ -25,17 +28,17 @@ pub async fn add_suggest_time(
     )
     .await?;
 
-    ctx.data()
+    guild
         .suggest_times
         .lock()
         .unwrap()
         .insert(time, label.clone());
-    data::save(ctx.data())?;
+    audit::save(ctx).await?;
 
     let title = format!("{}({})を追加しました", label, time.format("%H:%M"));
     let diff = format!(
         "```diff\n{}\n```",
-        ctx.data()
+        guild
             .suggest_times
             .lock()
             .unwrap()
@@ -66,13 +69,15 @@ pub async fn add_suggest_time(
     Ok(())
 }
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_editor")]
 /// よく使う時間を削除します。
+#[allow(clippy::collapsible_match)]
 pub async fn remove_suggest_time(ctx: PoiseContext<'_>) -> Result<(), Error> {
     const LABEL: &str = "label";
     const SUBMIT: &str = "submit";
 
-    let suggest_times = ctx.data().suggest_times.lock().unwrap().clone();
+    let guild = data::guild(ctx)?;
+    let suggest_times = guild.suggest_times.lock().unwrap().clone();
 
     let components = |selected_time: Option<NaiveTime>| {
         let suggest_time_options = CreateSelectMenuKind::String {
@@ -148,7 +153,7 @@ pub async fn remove_suggest_time(ctx: PoiseContext<'_>) -> Result<(), Error> {
     );
     let diff = format!(
         "```diff\n{}\n```",
-        ctx.data()
+        guild
             .suggest_times
             .lock()
             .unwrap()
@@ -163,8 +168,8 @@ pub async fn remove_suggest_time(ctx: PoiseContext<'_>) -> Result<(), Error> {
             .join("\n")
     );
 
-    ctx.data().suggest_times.lock().unwrap().remove(&time);
-    data::save(ctx.data())?;
+    guild.suggest_times.lock().unwrap().remove(&time);
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
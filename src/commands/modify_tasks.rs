@@ -1,15 +1,27 @@
-use anyhow::Error;
+use anyhow::{Context as _, Error};
 use poise::serenity_prelude::*;
 
 use crate::{
+    audit,
+    commands::permission::is_editor,
     data,
-    interactions::{create_task, select_task},
+    data::{parse_end, parse_weekdays, Frequency, Recurrence, RecurrenceEnd, TRASH_RETENTION_DAYS},
+    interactions::{create_task, select_scope, select_task, Scope},
     PartialTask, PoiseContext,
 };
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only)]
 /// タスクを追加します。
-pub async fn add_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
+pub async fn add_task(
+    ctx: PoiseContext<'_>,
+    #[description = "自分だけの個人のタスクにするか"] private: Option<bool>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
     let (last_interaction, task) = create_task(
         ctx,
         None,
@@ -18,12 +30,15 @@ pub async fn add_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
                 .title("タスクを追加します".to_string())
                 .color(Color::DARK_BLUE),
         ),
-        PartialTask::default(),
+        PartialTask {
+            owner,
+            ..Default::default()
+        },
     )
     .await?;
 
-    ctx.data().tasks.lock().unwrap().insert(task.clone());
-    data::save(ctx.data())?;
+    guild.tasks.lock().unwrap().insert(task.id, task.clone());
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
@@ -31,6 +46,7 @@ pub async fn add_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
                 CreateEmbed::default()
                     .title("タスクを追加しました")
                     .fields(vec![task.to_field()])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
                     .color(Color::DARK_GREEN),
             )
             .components(vec![]),
@@ -40,9 +56,18 @@ pub async fn add_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only)]
 /// タスクを削除します。
-pub async fn remove_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
+pub async fn remove_task(
+    ctx: PoiseContext<'_>,
+    #[description = "自分の個人のタスクから選ぶか"] private: Option<bool>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
     let (last_interaction, task) = select_task(
         ctx,
         None,
@@ -51,21 +76,64 @@ pub async fn remove_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
                 .title("削除するタスクを選択")
                 .color(Color::DARK_BLUE),
         ),
+        owner,
     )
     .await?;
 
-    {
-        let mut tasks = ctx.data().tasks.lock().unwrap();
-        tasks.remove(&task);
+    let (last_interaction, scope) = match task.occurrence {
+        Some(_) => {
+            select_scope(
+                ctx,
+                last_interaction,
+                Some(
+                    CreateEmbed::default()
+                        .title("繰り返しタスクのどの回を削除しますか？")
+                        .fields(vec![task.to_field()])
+                        .color(Color::DARK_BLUE),
+                ),
+            )
+            .await?
+        }
+        None => (last_interaction, Scope::Series),
+    };
+
+    match (scope, task.occurrence) {
+        (Scope::Occurrence, Some(date)) => {
+            guild
+                .tasks
+                .lock()
+                .unwrap()
+                .get_mut(&task.id)
+                .and_then(|task| task.recurrence.as_mut())
+                .context("Task not found")?
+                .exceptions
+                .insert(date, None);
+        }
+        _ => {
+            guild
+                .trash_task(task.id, ctx.author().id)
+                .context("Task not found")?;
+        }
     }
-    data::save(ctx.data())?;
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
             .embed(
                 CreateEmbed::default()
-                    .title("削除しました")
+                    .title(match scope {
+                        Scope::Occurrence => "この回をスキップしました",
+                        Scope::Series => "削除しました",
+                    })
+                    .description(match scope {
+                        Scope::Occurrence => "".to_string(),
+                        Scope::Series => format!(
+                            "{}日間はゴミ箱に残るので、/restore_task で元に戻せます",
+                            TRASH_RETENTION_DAYS
+                        ),
+                    })
                     .fields(vec![task.to_field()])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
                     .color(Color::DARK_RED),
             )
             .components(vec![]),
@@ -75,9 +143,18 @@ pub async fn remove_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only)]
 /// タスクを編集します。
-pub async fn edit_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
+pub async fn edit_task(
+    ctx: PoiseContext<'_>,
+    #[description = "自分の個人のタスクから選ぶか"] private: Option<bool>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
     let (last_interaction, task) = select_task(
         ctx,
         None,
@@ -86,9 +163,38 @@ pub async fn edit_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
                 .title("編集するタスクを選択")
                 .color(Color::DARK_BLUE),
         ),
+        owner,
     )
     .await?;
 
+    let (last_interaction, scope) = match task.occurrence {
+        Some(_) => {
+            select_scope(
+                ctx,
+                last_interaction,
+                Some(
+                    CreateEmbed::default()
+                        .title("繰り返しタスクのどの回を編集しますか？")
+                        .fields(vec![task.to_field()])
+                        .color(Color::DARK_BLUE),
+                ),
+            )
+            .await?
+        }
+        None => (last_interaction, Scope::Series),
+    };
+    // すべての回を編集するときは、最初の回を元にする
+    let task = match scope {
+        Scope::Occurrence => task,
+        Scope::Series => guild
+            .tasks
+            .lock()
+            .unwrap()
+            .get(&task.id)
+            .cloned()
+            .context("Task not found")?,
+    };
+
     let (last_interaction, modified_task) = create_task(
         ctx,
         Some(last_interaction),
@@ -102,11 +208,21 @@ pub async fn edit_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
     .await?;
 
     {
-        let mut tasks = ctx.data().tasks.lock().unwrap();
-        tasks.remove(&task);
-        tasks.insert(modified_task.clone());
+        let mut tasks = guild.tasks.lock().unwrap();
+        let stored = tasks.get_mut(&task.id).context("Task not found")?;
+        match task.occurrence {
+            Some(date) => {
+                stored
+                    .recurrence
+                    .as_mut()
+                    .context("Task not found")?
+                    .exceptions
+                    .insert(date, Some(modified_task.clone()));
+            }
+            None => *stored = modified_task.clone(),
+        }
     }
-    data::save(ctx.data())?;
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
@@ -118,6 +234,109 @@ pub async fn edit_task(ctx: PoiseContext<'_>) -> Result<(), Error> {
                         ("↓".into(), "".into(), false),
                         modified_task.to_field(),
                     ])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
+                    .color(Color::DARK_GREEN),
+            )
+            .components(vec![]),
+    );
+    last_interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum Repeat {
+    #[name = "毎日"]
+    Daily,
+    #[name = "毎週"]
+    Weekly,
+    #[name = "繰り返さない"]
+    Never,
+}
+
+#[poise::command(slash_command, guild_only)]
+/// タスクを繰り返すように設定します。
+pub async fn set_recurrence(
+    ctx: PoiseContext<'_>,
+    #[description = "繰り返し方"] repeat: Repeat,
+    #[description = "毎週の場合の曜日(例: 月,水) / 省略すると最初の回と同じ曜日"] weekdays: Option<
+        String,
+    >,
+    #[description = "何日ごと、何週ごとか(省略すると1)"]
+    #[min = 1]
+    #[max = 52]
+    interval: Option<u32>,
+    #[description = "この日まで(例: 2025/03/31)か、この回数だけ(例: 10)繰り返す"] end: Option<
+        String,
+    >,
+    #[description = "年間行事予定で授業の無い日は飛ばすか"] school_days_only: Option<bool>,
+    #[description = "自分の個人のタスクに設定するか"] private: Option<bool>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
+    let end = end
+        .as_deref()
+        .map(parse_end)
+        .transpose()?
+        .unwrap_or(RecurrenceEnd::Never);
+    let frequency = match repeat {
+        Repeat::Daily => Some(Frequency::Daily),
+        Repeat::Weekly => Some(Frequency::Weekly(
+            weekdays
+                .as_deref()
+                .map(parse_weekdays)
+                .transpose()?
+                .unwrap_or_default(),
+        )),
+        Repeat::Never => None,
+    };
+
+    let (last_interaction, task) = select_task(
+        ctx,
+        None,
+        Some(
+            CreateEmbed::default()
+                .title("繰り返しを設定するタスクを選択")
+                .color(Color::DARK_BLUE),
+        ),
+        owner,
+    )
+    .await?;
+
+    let task = {
+        let mut tasks = guild.tasks.lock().unwrap();
+        let task = tasks.get_mut(&task.id).context("Task not found")?;
+        // 個別に編集・スキップした回の設定は引き継ぐ
+        let exceptions = task
+            .recurrence
+            .take()
+            .map(|recurrence| recurrence.exceptions)
+            .unwrap_or_default();
+        task.recurrence = frequency.map(|frequency| Recurrence {
+            frequency,
+            interval: interval.unwrap_or(1),
+            end,
+            exceptions,
+            school_days_only: school_days_only.unwrap_or(false),
+        });
+        task.clone()
+    };
+    audit::save(ctx).await?;
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(
+                CreateEmbed::default()
+                    .title(match task.recurrence {
+                        Some(_) => "繰り返しを設定しました",
+                        None => "繰り返しを解除しました",
+                    })
+                    .fields(vec![task.to_field()])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
                     .color(Color::DARK_GREEN),
             )
             .components(vec![]),
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{Context as _, Error};
 use chrono::Local;
@@ -6,13 +6,20 @@ use itertools::Itertools;
 use poise::serenity_prelude::*;
 use {futures::StreamExt, Mentionable};
 
-use crate::{data, PoiseContext};
+use crate::{
+    audit,
+    commands::permission::is_admin,
+    data::{self, GuildData, RECURRENCE_HORIZON_DAYS, RECURRENCE_PAST_DAYS},
+    utilities::format_date,
+    Data, PoiseContext,
+};
 
 const TASKS: &str = "tasks";
 const ARCHIVED_TASKS: &str = "archived_tasks";
+const TODAY_LESSONS: &str = "today_lessons";
 const TASKS_PER_PAGE: usize = 7;
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_admin")]
 /// パネルをデプロイします。
 pub async fn deploy_panel(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let message = ctx
@@ -33,27 +40,34 @@ pub async fn deploy_panel(ctx: PoiseContext<'_>) -> Result<(), Error> {
                     CreateButton::new(ARCHIVED_TASKS)
                         .label("過去のタスク一覧")
                         .style(ButtonStyle::Secondary),
+                    CreateButton::new(TODAY_LESSONS)
+                        .label("今日の授業")
+                        .style(ButtonStyle::Primary),
                 ])]),
         )
         .await?;
 
     let id_pair = (message.id, message.channel_id);
+    let guild_id = ctx.guild_id().context("Not in a guild")?;
+    let guild = ctx.data().guild(guild_id);
 
-    ctx.data().panel_message.lock().unwrap().replace(id_pair);
-    data::save(ctx.data())?;
+    guild.panel_message.lock().unwrap().replace(id_pair);
+    audit::save(ctx).await?;
 
-    ctx.data()
+    guild
         .panel_listener
         .lock()
         .unwrap()
         .as_ref()
         .inspect(|h| h.abort());
-    ctx.data()
+    guild
         .panel_listener
         .lock()
         .unwrap()
         .replace(tokio::spawn(listen_panel_interactions(
             ctx.serenity_context().clone(),
+            ctx.data().clone(),
+            guild_id,
             id_pair,
         )));
 
@@ -73,6 +87,8 @@ pub async fn deploy_panel(ctx: PoiseContext<'_>) -> Result<(), Error> {
 
 pub async fn listen_panel_interactions(
     ctx: Context,
+    data: Arc<Data>,
+    guild_id: GuildId,
     id_pair: (MessageId, ChannelId),
 ) -> Result<(), Error> {
     let (message_id, channel_id) = id_pair;
@@ -82,10 +98,26 @@ pub async fn listen_panel_interactions(
     while let Some(interaction) = interaction_stream.next().await {
         match interaction.data.custom_id.as_str() {
             TASKS => {
-                tokio::spawn(show_tasks(interaction.clone(), ctx.clone()));
+                tokio::spawn(show_tasks(
+                    interaction.clone(),
+                    ctx.clone(),
+                    data.clone(),
+                    data.guild(guild_id),
+                ));
             }
             ARCHIVED_TASKS => {
-                tokio::spawn(show_archived_tasks(interaction.clone(), ctx.clone()));
+                tokio::spawn(show_archived_tasks(
+                    interaction.clone(),
+                    ctx.clone(),
+                    data.guild(guild_id),
+                ));
+            }
+            TODAY_LESSONS => {
+                tokio::spawn(show_today_lessons(
+                    interaction.clone(),
+                    ctx.clone(),
+                    data.guild(guild_id),
+                ));
             }
             _ => {}
         }
@@ -94,8 +126,13 @@ pub async fn listen_panel_interactions(
     Ok(())
 }
 
-async fn log(ctx: &Context, user: &User, message: impl Into<String>) -> Result<(), Error> {
-    let log_channel = *data::load()?.log_channel.lock().unwrap();
+async fn log(
+    ctx: &Context,
+    guild: &GuildData,
+    user: &User,
+    message: impl Into<String>,
+) -> Result<(), Error> {
+    let log_channel = *guild.log_channel.lock().unwrap();
 
     log_channel
         .context("log channel not set")?
@@ -119,52 +156,107 @@ async fn log(ctx: &Context, user: &User, message: impl Into<String>) -> Result<(
     Ok(())
 }
 
-async fn show_tasks(interaction: ComponentInteraction, ctx: Context) -> Result<(), Error> {
+async fn show_tasks(
+    interaction: ComponentInteraction,
+    ctx: Context,
+    data: Arc<Data>,
+    guild: Arc<GuildData>,
+) -> Result<(), Error> {
     const PREV: &str = "prev";
     const NEXT: &str = "next";
+    const TOGGLE_DONE: &str = "toggle_done";
+    const HIDE_DONE: &str = "hide_done";
 
-    let tasks = data::load()?.tasks.lock().unwrap().clone();
+    let tasks = guild.occurrences(
+        Local::now(),
+        Local::now() + chrono::Duration::days(RECURRENCE_HORIZON_DAYS),
+    );
+    let user = interaction.user.id;
 
     let mut page = 0;
-    let message = |page: usize| {
-        let fields = tasks
+    let mut hide_done = false;
+    let message = |page: usize, hide_done: bool| {
+        let tasks = tasks
             .iter()
             .filter(|e| Local::now().date_naive() <= e.datetime.date_naive())
+            .filter(|e| e.visible_to(user))
+            .filter(|e| !(hide_done && guild.is_done(user, e)))
             .sorted_by_key(|e| e.datetime)
-            .map(|task| task.to_field())
-            .skip(TASKS_PER_PAGE * page);
+            .skip(TASKS_PER_PAGE * page)
+            .collect::<Vec<_>>();
+        let page_tasks = tasks.iter().take(TASKS_PER_PAGE).collect::<Vec<_>>();
+
+        let mut components = vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(PREV)
+                .label("前のページ")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0),
+            CreateButton::new(NEXT)
+                .label("次のページ")
+                .style(ButtonStyle::Secondary)
+                .disabled(tasks.len() <= TASKS_PER_PAGE),
+            CreateButton::new(HIDE_DONE)
+                .label(if hide_done {
+                    "完了済みを表示"
+                } else {
+                    "完了済みを隠す"
+                })
+                .style(ButtonStyle::Secondary),
+        ])];
+        if !page_tasks.is_empty() {
+            let options = page_tasks
+                .iter()
+                .map(|task| {
+                    let label = task.to_field().0;
+                    CreateSelectMenuOption::new(
+                        if guild.is_done(user, task) {
+                            format!("✅ {}", label)
+                        } else {
+                            label
+                        },
+                        task.key(),
+                    )
+                })
+                .collect();
+            components.push(CreateActionRow::SelectMenu(
+                CreateSelectMenu::new(TOGGLE_DONE, CreateSelectMenuKind::String { options })
+                    .placeholder("完了にする / 未完了に戻す"),
+            ));
+        }
 
         CreateInteractionResponseMessage::new()
             .embed(
                 CreateEmbed::default()
                     .title("タスク一覧")
-                    .description(if fields.len() == 0 {
+                    .description(if tasks.is_empty() {
                         "ありません！:tada:"
                     } else {
                         ""
                     })
-                    .fields(fields.clone().take(TASKS_PER_PAGE))
+                    .fields(page_tasks.iter().map(|task| {
+                        let (name, value, inline) = task.to_field();
+                        if guild.is_done(user, task) {
+                            (format!("✅ ~~{}~~", name), value, inline)
+                        } else {
+                            (name, value, inline)
+                        }
+                    }))
                     .color(Color::DARK_BLUE),
             )
-            .components(vec![CreateActionRow::Buttons(vec![
-                CreateButton::new(PREV)
-                    .label("前のページ")
-                    .style(ButtonStyle::Secondary)
-                    .disabled(page == 0),
-                CreateButton::new(NEXT)
-                    .label("次のページ")
-                    .style(ButtonStyle::Secondary)
-                    .disabled(fields.len() <= TASKS_PER_PAGE),
-            ])])
+            .components(components)
             .ephemeral(true)
     };
 
     interaction
-        .create_response(&ctx, CreateInteractionResponse::Message(message(page)))
+        .create_response(
+            &ctx,
+            CreateInteractionResponse::Message(message(page, hide_done)),
+        )
         .await?;
 
     log(
         &ctx,
+        &guild,
         &interaction.user,
         format!(
             "{}さんがタスク一覧を確認しました",
@@ -181,43 +273,53 @@ async fn show_tasks(interaction: ComponentInteraction, ctx: Context) -> Result<(
         .stream();
 
     while let Some(interaction) = interaction_stream.next().await {
-        match interaction.data.custom_id.as_str() {
-            PREV => {
-                page = page.saturating_sub(1);
-                interaction
-                    .create_response(
-                        &ctx,
-                        CreateInteractionResponse::UpdateMessage(message(page)),
-                    )
-                    .await?;
+        match &interaction.data.kind {
+            ComponentInteractionDataKind::StringSelect { values }
+                if interaction.data.custom_id == TOGGLE_DONE =>
+            {
+                if let Some(task) = guild.find_task(&values[0]) {
+                    guild.toggle_done(user, &task);
+                    data::save(&data)?;
+                }
             }
-            NEXT => {
-                page += 1;
-                interaction
-                    .create_response(
-                        &ctx,
-                        CreateInteractionResponse::UpdateMessage(message(page)),
-                    )
-                    .await?;
-            }
-            _ => {}
+            _ => match interaction.data.custom_id.as_str() {
+                PREV => page = page.saturating_sub(1),
+                NEXT => page += 1,
+                HIDE_DONE => hide_done = !hide_done,
+                _ => continue,
+            },
         }
+        interaction
+            .create_response(
+                &ctx,
+                CreateInteractionResponse::UpdateMessage(message(page, hide_done)),
+            )
+            .await?;
     }
 
     Ok(())
 }
 
-async fn show_archived_tasks(interaction: ComponentInteraction, ctx: Context) -> Result<(), Error> {
+async fn show_archived_tasks(
+    interaction: ComponentInteraction,
+    ctx: Context,
+    guild: Arc<GuildData>,
+) -> Result<(), Error> {
     const PREV: &str = "prev";
     const NEXT: &str = "next";
 
-    let tasks = data::load()?.tasks.lock().unwrap().clone();
+    let tasks = guild.occurrences(
+        Local::now() - chrono::Duration::days(RECURRENCE_PAST_DAYS),
+        Local::now(),
+    );
+    let user = interaction.user.id;
 
     let mut page = 0;
     let message = |page: usize| {
         let fields = tasks
             .iter()
             .filter(|e| Local::now() > e.datetime)
+            .filter(|e| e.visible_to(user))
             .sorted_by_key(|e| e.datetime)
             .rev()
             .map(|task| task.to_field())
@@ -254,6 +356,7 @@ async fn show_archived_tasks(interaction: ComponentInteraction, ctx: Context) ->
 
     log(
         &ctx,
+        &guild,
         &interaction.user,
         format!(
             "{}さんが過去のタスク一覧を確認しました",
@@ -295,3 +398,52 @@ async fn show_archived_tasks(interaction: ComponentInteraction, ctx: Context) ->
 
     Ok(())
 }
+
+async fn show_today_lessons(
+    interaction: ComponentInteraction,
+    ctx: Context,
+    guild: Arc<GuildData>,
+) -> Result<(), Error> {
+    let today = Local::now().date_naive();
+    let lessons = {
+        let calendar = guild.school_calendar.lock().unwrap();
+        let special_day = calendar.special_day(today).map(|d| format!("{}\n", d.name));
+        match calendar.timetable_weekday(today) {
+            Some(weekday) => format!(
+                "{}{}",
+                special_day.unwrap_or_default(),
+                guild.timetable.lock().unwrap().describe_day(weekday)
+            ),
+            None => "今日は授業がありません".to_string(),
+        }
+    };
+
+    interaction
+        .create_response(
+            &ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .embed(
+                        CreateEmbed::default()
+                            .title(format!("今日の授業 ({})", format_date(today)))
+                            .description(lessons)
+                            .color(Color::DARK_BLUE),
+                    )
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    log(
+        &ctx,
+        &guild,
+        &interaction.user,
+        format!(
+            "{}さんが今日の授業を確認しました",
+            interaction.user.mention()
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context as _, Error};
+use poise::serenity_prelude::*;
+
+use crate::{audit, data, PoiseContext};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Member,
+    Editor,
+    Admin,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Member => "メンバー",
+            Level::Editor => "編集者",
+            Level::Admin => "管理者",
+        }
+    }
+}
+
+// サーバーの管理権限を持つ人は常に管理者として扱う
+// 編集者のロールが1つも設定されていなければ、これまで通り誰でも編集できる
+async fn level(ctx: PoiseContext<'_>) -> Result<Level, Error> {
+    let guild = data::guild(ctx)?;
+    let member = ctx.author_member().await.context("Not a member")?;
+    let admin_roles = guild.admin_roles.lock().unwrap().clone();
+    let editor_roles = guild.editor_roles.lock().unwrap().clone();
+
+    if member
+        .permissions
+        .is_some_and(|p| p.administrator() || p.manage_guild())
+        || member.roles.iter().any(|r| admin_roles.contains(r))
+    {
+        Ok(Level::Admin)
+    } else if editor_roles.is_empty() || member.roles.iter().any(|r| editor_roles.contains(r)) {
+        Ok(Level::Editor)
+    } else {
+        Ok(Level::Member)
+    }
+}
+
+// 足りなければ本人にだけ見えるように断る
+async fn require(ctx: PoiseContext<'_>, required: Level) -> Result<bool, Error> {
+    if level(ctx).await? >= required {
+        return Ok(true);
+    }
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("権限がありません")
+                    .description(format!(
+                        "このコマンドは{}のロールを持つメンバーだけが使えます",
+                        required.name()
+                    ))
+                    .color(Color::DARK_RED),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(false)
+}
+
+/// クラス全体のタスクや教科などを変更できるか
+pub async fn is_editor(ctx: PoiseContext<'_>) -> Result<bool, Error> {
+    require(ctx, Level::Editor).await
+}
+
+/// 通知先や権限などの設定を変更できるか
+pub async fn is_admin(ctx: PoiseContext<'_>) -> Result<bool, Error> {
+    require(ctx, Level::Admin).await
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum Permission {
+    #[name = "管理者"]
+    Admin,
+    #[name = "編集者"]
+    Editor,
+    #[name = "なし"]
+    None,
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// ロールに管理者・編集者の権限を設定します。
+pub async fn set_role_permission(
+    ctx: PoiseContext<'_>,
+    #[description = "設定するロール"] role: Role,
+    #[description = "与える権限"] permission: Permission,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    {
+        let mut admin_roles = guild.admin_roles.lock().unwrap();
+        let mut editor_roles = guild.editor_roles.lock().unwrap();
+        admin_roles.remove(&role.id);
+        editor_roles.remove(&role.id);
+        match permission {
+            Permission::Admin => admin_roles.insert(role.id),
+            Permission::Editor => editor_roles.insert(role.id),
+            Permission::None => false,
+        };
+    }
+    audit::save(ctx).await?;
+
+    let mention = |roles: &BTreeSet<RoleId>| {
+        if roles.is_empty() {
+            "(なし)".to_string()
+        } else {
+            roles
+                .iter()
+                .map(|r| r.mention().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    };
+    let admins = mention(&guild.admin_roles.lock().unwrap());
+    let editors = mention(&guild.editor_roles.lock().unwrap());
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title("権限を設定しました")
+                .field("管理者", admins, false)
+                .field("編集者", editors, false)
+                .color(Color::DARK_BLUE),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
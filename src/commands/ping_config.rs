@@ -4,17 +4,14 @@ use anyhow::{Context as _, Error};
 use futures::StreamExt;
 use poise::serenity_prelude::*;
 
-use crate::{data, PoiseContext};
+use crate::{audit, commands::permission::is_admin, data, interactions::select_time, PoiseContext};
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_admin")]
 /// タスク通知を送るチャンネルを設定します。
 pub async fn set_ping_channel(ctx: PoiseContext<'_>) -> Result<(), Error> {
-    ctx.data()
-        .ping_channel
-        .lock()
-        .unwrap()
-        .replace(ctx.channel_id());
-    data::save(ctx.data())?;
+    let guild = data::guild(ctx)?;
+    guild.ping_channel.lock().unwrap().replace(ctx.channel_id());
+    audit::save(ctx).await?;
 
     ctx.send(
         poise::CreateReply::default().embed(
@@ -29,12 +26,14 @@ pub async fn set_ping_channel(ctx: PoiseContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-#[poise::command(slash_command)]
+#[poise::command(slash_command, guild_only, check = "is_admin")]
 /// タスク通知を送るロールを設定します。
+#[allow(clippy::collapsible_match)]
 pub async fn set_ping_role(ctx: PoiseContext<'_>) -> Result<(), Error> {
     const ROLE: &str = "role";
     const SUBMIT: &str = "submit";
 
+    let guild = data::guild(ctx)?;
     let components = |role: Option<RoleId>| {
         vec![
             CreateActionRow::SelectMenu(
@@ -96,12 +95,12 @@ pub async fn set_ping_role(ctx: PoiseContext<'_>) -> Result<(), Error> {
             _ => {}
         }
     }
-    ctx.data()
+    guild
         .ping_role
         .lock()
         .unwrap()
         .replace(select.context("No role selected")?);
-    data::save(ctx.data())?;
+    audit::save(ctx).await?;
 
     let response = CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::default()
@@ -121,3 +120,62 @@ pub async fn set_ping_role(ctx: PoiseContext<'_>) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// タスク通知を送る時刻を設定します。
+pub async fn set_notify_time(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let (interaction, time) = select_time(
+        ctx,
+        None,
+        Some(
+            CreateEmbed::default()
+                .title("通知時刻を選択してください")
+                .color(Color::DARK_BLUE),
+        ),
+    )
+    .await?;
+
+    guild.notify.lock().unwrap().time = time;
+    audit::save(ctx).await?;
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("通知時刻を設定しました")
+                    .description(format!("毎日 {} に通知します", time.format("%H:%M")))
+                    .color(Color::DARK_BLUE),
+            )
+            .components(vec![]),
+    );
+    interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// 何日先までのタスクを通知するかを設定します。
+pub async fn set_notify_days(
+    ctx: PoiseContext<'_>,
+    #[description = "明日から何日間のタスクを通知するか(1なら明日のみ)"]
+    #[min = 1]
+    #[max = 30]
+    days: u32,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    guild.notify.lock().unwrap().days = days;
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title("通知する期間を設定しました")
+                .description(format!("明日から{}日間のタスクを通知します", days))
+                .color(Color::DARK_BLUE),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,505 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context as _, Error};
+use chrono::{Datelike, Days, Duration, Local, NaiveDate, NaiveTime, Weekday};
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_editor,
+    data::{self, parse_weekdays, Period},
+    interactions::create_task,
+    Category, PartialTask, PoiseContext, Subject, Task,
+};
+
+// カテゴリーの表示名や英語名のほかに受け付ける言葉
+const CATEGORY_WORDS: [(&str, Category); 12] = [
+    ("行事", Category::Event),
+    ("試験", Category::Exam),
+    ("小テスト", Category::Exam),
+    ("test", Category::Exam),
+    ("quiz", Category::Exam),
+    ("課題", Category::Homework),
+    ("提出", Category::Homework),
+    ("hw", Category::Homework),
+    ("assignment", Category::Homework),
+    ("持参", Category::Belongings),
+    ("bring", Category::Belongings),
+    ("memo", Category::Other),
+];
+
+/// 文章から日付や時刻を探すときに、1つの表現として見る最大の文字数
+const FIND_MAX_CHARS: usize = 12;
+/// メッセージから作るタスクの詳細の最大の文字数
+const DETAILS_MAX_CHARS: usize = 200;
+
+/// 1行の文章からタスクの各項目を読み取ります。
+struct Parser<'a> {
+    today: NaiveDate,
+    subjects: &'a BTreeSet<String>,
+    suggest_times: &'a BTreeMap<NaiveTime, String>,
+    periods: &'a BTreeMap<u32, Period>,
+}
+
+impl Parser<'_> {
+    /// 空白で区切った語をそれぞれカテゴリー・教科・日付・時刻として読み、どれでもない語は詳細にします。
+    /// 同じ項目が2回出てきたら、2回目からは詳細として扱います。
+    fn parse(&self, text: &str) -> PartialTask {
+        let mut task = PartialTask::default();
+        let mut details = vec![];
+        for word in text.split_whitespace() {
+            if let (None, Some(category)) = (task.category, self.category(word)) {
+                task.category = Some(category);
+            } else if let (None, Some(subject)) = (&task.subject, self.subject(word)) {
+                task.subject = Some(Subject::Set(subject));
+            } else if let (None, Some(date)) = (task.date, self.date(word)) {
+                task.date = Some(date);
+            } else if let (None, Some(time)) = (task.time, self.time(word)) {
+                task.time = Some(time);
+            } else {
+                details.push(word);
+            }
+        }
+        if !details.is_empty() {
+            task.details = Some(details.join(" "));
+        }
+        task
+    }
+
+    /// 文章の中からカテゴリー・教科・日付・時刻を探します。詳細は読み取りません。
+    fn guess(&self, text: &str) -> PartialTask {
+        // 英字の語は単語ごとに比べ、日本語の語は文章に含まれているかで探す
+        let words = text
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>();
+        let contains = |name: &str| {
+            if name.is_ascii() {
+                words.iter().any(|w| w.eq_ignore_ascii_case(name))
+            } else {
+                text.contains(name)
+            }
+        };
+        let category = Category::VALUES
+            .into_iter()
+            .find(|&c| contains(&String::from(c)))
+            .or_else(|| {
+                CATEGORY_WORDS
+                    .iter()
+                    .find(|(w, _)| contains(w))
+                    .map(|&(_, category)| category)
+            });
+        let subject = self
+            .subjects
+            .iter()
+            .find(|s| contains(s))
+            .map(|s| Subject::Set(s.clone()));
+
+        PartialTask {
+            category,
+            subject,
+            date: find(text, |s| self.date(s)),
+            time: find(text, |s| self.time(s)),
+            ..Default::default()
+        }
+    }
+
+    fn category(&self, word: &str) -> Option<Category> {
+        Category::from_name(word).or_else(|| {
+            CATEGORY_WORDS
+                .iter()
+                .find(|(w, _)| w.eq_ignore_ascii_case(word))
+                .map(|&(_, category)| category)
+        })
+    }
+
+    fn subject(&self, word: &str) -> Option<String> {
+        self.subjects
+            .iter()
+            .find(|s| s.eq_ignore_ascii_case(word))
+            .cloned()
+    }
+
+    fn date(&self, word: &str) -> Option<NaiveDate> {
+        let today = self.today;
+        match word.to_lowercase().as_str() {
+            "今日" | "きょう" | "today" => return Some(today),
+            "明日" | "あした" | "tomorrow" => return today.succ_opt(),
+            "明後日" | "あさって" => return today.checked_add_days(Days::new(2)),
+            _ => {}
+        }
+        if let Some(days) = word.strip_suffix("日後") {
+            return today.checked_add_days(Days::new(days.parse().ok()?));
+        }
+        if let Some(date) = self.weekday(word) {
+            return Some(date);
+        }
+
+        // 年を省いたときは、今日以降で最も近い日にする
+        let (year, month, day) = match word.split(['/', '-', '月', '日']).collect::<Vec<_>>()[..]
+        {
+            [year, month, day] if word.contains(['/', '-']) => {
+                (Some(year.parse().ok()?), month, day)
+            }
+            [month, day] if word.contains('/') => (None, month, day),
+            [month, day, ""] if word.ends_with('日') => (None, month, day),
+            _ => return None,
+        };
+        let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+        match year {
+            Some(year) => NaiveDate::from_ymd_opt(year, month, day),
+            None => NaiveDate::from_ymd_opt(today.year(), month, day)
+                .filter(|date| today <= *date)
+                .or_else(|| NaiveDate::from_ymd_opt(today.year() + 1, month, day)),
+        }
+    }
+
+    // 「月曜」は明日以降で最も近い月曜日、「今週月曜」「来週月曜」「再来週月曜」は月曜始まりの週で数える
+    fn weekday(&self, word: &str) -> Option<NaiveDate> {
+        let (weeks, name) = [("再来週", 2), ("来週", 1), ("今週", 0)]
+            .into_iter()
+            .find_map(|(prefix, weeks)| Some((Some(weeks), word.strip_prefix(prefix)?)))
+            .unwrap_or((None, word));
+        if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let weekday: Weekday = match name.parse() {
+            Ok(weekday) => weekday,
+            // 「月」だけでは月の意味と紛らわしいので、曜を付けたときだけ日本語の曜日として読む
+            Err(_) if name.contains('曜') => match parse_weekdays(name).ok()?[..] {
+                [weekday] => weekday,
+                _ => return None,
+            },
+            Err(_) => return None,
+        };
+        match weeks {
+            Some(weeks) => self
+                .today
+                .week(Weekday::Mon)
+                .first_day()
+                .checked_add_days(Days::new(weeks * 7 + weekday.num_days_from_monday() as u64)),
+            None => (1..=7)
+                .filter_map(|i| self.today.checked_add_days(Days::new(i)))
+                .find(|date| date.weekday() == weekday),
+        }
+    }
+
+    fn time(&self, word: &str) -> Option<NaiveTime> {
+        if let Some(period) = word.strip_suffix("限") {
+            return Some(self.periods.get(&period.parse().ok()?)?.start);
+        }
+        if let Some((&time, _)) = self.suggest_times.iter().find(|(_, l)| *l == word) {
+            return Some(time);
+        }
+        if let Ok(time) = NaiveTime::parse_from_str(word, "%H:%M") {
+            return Some(time);
+        }
+        let (hour, minute) = word.strip_suffix('分').unwrap_or(word).split_once('時')?;
+        let minute = match minute {
+            "" => 0,
+            "半" => 30,
+            minute => minute.parse().ok()?,
+        };
+        NaiveTime::from_hms_opt(hour.parse().ok()?, minute, 0)
+    }
+}
+
+/// 文章の中で最初に`parse`で読み取れる部分を探します。同じ位置からなら長い方を優先します。
+fn find<T>(text: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    // 「10/21」の「0/21」や「1時間」の「1時」のように、語の途中で切れるものは除く
+    let joined = |a: char, b: char| {
+        (a.is_ascii_alphanumeric() && b.is_ascii_alphanumeric()) || (a == '時' && b == '間')
+    };
+    let chars = text.char_indices().collect::<Vec<_>>();
+    for start in 0..chars.len() {
+        if start > 0 && joined(chars[start - 1].1, chars[start].1) {
+            continue;
+        }
+        for end in (start + 1..=(start + FIND_MAX_CHARS).min(chars.len())).rev() {
+            if end < chars.len() && joined(chars[end - 1].1, chars[end].1) {
+                continue;
+            }
+            let byte_end = chars.get(end).map_or(text.len(), |&(i, _)| i);
+            if let Some(value) = parse(&text[chars[start].0..byte_end]) {
+                return Some(value);
+            }
+        }
+    }
+    None
+}
+
+fn added(task: &Task) -> CreateInteractionResponse {
+    CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("タスクを追加しました")
+                    .fields(vec![task.to_field()])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
+                    .color(Color::DARK_GREEN),
+            )
+            .components(vec![]),
+    )
+}
+
+#[poise::command(slash_command, guild_only)]
+/// 「数学 宿題 p.34-36 来週月曜 1限」のような1行からタスクを追加します。
+pub async fn quick_add(
+    ctx: PoiseContext<'_>,
+    #[description = "教科・カテゴリー・日付・時刻・詳細を空白で区切って入力"] text: String,
+    #[description = "自分だけの個人のタスクにするか"] private: Option<bool>,
+) -> Result<(), Error> {
+    const ADD: &str = "add";
+    const EDIT: &str = "edit";
+    const CANCEL: &str = "cancel";
+
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
+
+    let partial = {
+        let subjects = guild.subjects.lock().unwrap();
+        let suggest_times = guild.suggest_times.lock().unwrap();
+        let timetable = guild.timetable.lock().unwrap();
+        PartialTask {
+            owner,
+            ..Parser {
+                today: Local::now().date_naive(),
+                subjects: &subjects,
+                suggest_times: &suggest_times,
+                periods: &timetable.periods,
+            }
+            .parse(&text)
+        }
+    };
+    let embed = |title: &str| {
+        CreateEmbed::default()
+            .title(title.to_string())
+            .description(format!("入力: {}", text))
+            .color(Color::DARK_BLUE)
+    };
+    // 読み取れなかった項目があれば、読み取れた分を入れた状態で普段の入力画面に進む
+    let Ok(task) = partial.unpartial() else {
+        let (last_interaction, task) = create_task(
+            ctx,
+            None,
+            Some(embed("足りない項目を選択してください")),
+            partial,
+        )
+        .await?;
+        guild.tasks.lock().unwrap().insert(task.id, task.clone());
+        audit::save(ctx).await?;
+        last_interaction.create_response(ctx, added(&task)).await?;
+        return Ok(());
+    };
+
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .embed(embed("このタスクを追加しますか？").fields(vec![task.to_field()]))
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(ADD)
+                        .label("追加する")
+                        .style(ButtonStyle::Primary),
+                    CreateButton::new(EDIT)
+                        .label("修正する")
+                        .style(ButtonStyle::Secondary),
+                    CreateButton::new(CANCEL)
+                        .label("キャンセル")
+                        .style(ButtonStyle::Secondary),
+                ])])
+                .ephemeral(owner.is_some()),
+        )
+        .await?;
+
+    let interaction = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .timeout(Duration::seconds(60 * 30).to_std()?)
+        .await
+        .context("No interaction")?;
+
+    match interaction.data.custom_id.as_str() {
+        ADD => {
+            guild.tasks.lock().unwrap().insert(task.id, task.clone());
+            audit::save(ctx).await?;
+            interaction.create_response(ctx, added(&task)).await?;
+        }
+        EDIT => {
+            let (last_interaction, task) = create_task(
+                ctx,
+                Some(interaction),
+                Some(embed("タスクを修正します")),
+                partial,
+            )
+            .await?;
+            guild.tasks.lock().unwrap().insert(task.id, task.clone());
+            audit::save(ctx).await?;
+            last_interaction.create_response(ctx, added(&task)).await?;
+        }
+        _ => {
+            let response = CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::default()
+                    .embed(
+                        CreateEmbed::default()
+                            .title("追加をキャンセルしました")
+                            .color(Color::DARK_GREEN),
+                    )
+                    .components(vec![]),
+            );
+            interaction.create_response(ctx, response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[poise::command(context_menu_command = "タスクにする", guild_only, check = "is_editor")]
+/// メッセージの内容からタスクを追加します。
+pub async fn add_task_from_message(
+    ctx: PoiseContext<'_>,
+    #[description = "タスクにするメッセージ"] message: Message,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    // コンテキストメニューで渡されるメッセージにはサーバーIDが入っていない
+    let link = message.id.link(message.channel_id, ctx.guild_id());
+    let content = message
+        .content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    // 埋め込みのフィールド名に収まるように短くする
+    let details = if content.chars().count() > DETAILS_MAX_CHARS {
+        format!(
+            "{}…",
+            content.chars().take(DETAILS_MAX_CHARS).collect::<String>()
+        )
+    } else {
+        content
+    };
+
+    let partial = {
+        let subjects = guild.subjects.lock().unwrap();
+        let suggest_times = guild.suggest_times.lock().unwrap();
+        let timetable = guild.timetable.lock().unwrap();
+        PartialTask {
+            details: (!details.is_empty()).then_some(details),
+            source: Some(link.clone()),
+            ..Parser {
+                today: Local::now().date_naive(),
+                subjects: &subjects,
+                suggest_times: &suggest_times,
+                periods: &timetable.periods,
+            }
+            .guess(&message.content)
+        }
+    };
+
+    let (last_interaction, task) = create_task(
+        ctx,
+        None,
+        Some(
+            CreateEmbed::default()
+                .title("メッセージからタスクを追加します")
+                .description(link)
+                .color(Color::DARK_BLUE),
+        ),
+        partial,
+    )
+    .await?;
+    guild.tasks.lock().unwrap().insert(task.id, task.clone());
+    audit::save(ctx).await?;
+    last_interaction.create_response(ctx, added(&task)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser<T>(f: impl FnOnce(&Parser) -> T) -> T {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let subjects = BTreeSet::from(["数学".to_string(), "English".to_string()]);
+        let suggest_times = BTreeMap::from([(time(8, 30), "朝".to_string())]);
+        let periods = BTreeMap::from([(
+            1,
+            Period {
+                start: time(8, 50),
+                end: time(9, 40),
+            },
+        )]);
+        let parser = Parser {
+            // 水曜日
+            today: NaiveDate::from_ymd_opt(2024, 4, 3).unwrap(),
+            subjects: &subjects,
+            suggest_times: &suggest_times,
+            periods: &periods,
+        };
+        f(&parser)
+    }
+
+    fn parse(text: &str) -> PartialTask {
+        parser(|p| p.parse(text))
+    }
+
+    #[test]
+    fn parses_japanese_line() {
+        let task = parse("数学 宿題 p.34-36 来週月曜 1限");
+        assert_eq!(task.category, Some(Category::Homework));
+        assert_eq!(task.subject, Some(Subject::Set("数学".into())));
+        assert_eq!(task.details.as_deref(), Some("p.34-36"));
+        assert_eq!(task.date, NaiveDate::from_ymd_opt(2024, 4, 8));
+        assert_eq!(task.time, NaiveTime::from_hms_opt(8, 50, 0));
+    }
+
+    #[test]
+    fn parses_english_line() {
+        let task = parse("exam english 10/21 9:00 unit 3");
+        assert_eq!(task.category, Some(Category::Exam));
+        assert_eq!(task.subject, Some(Subject::Set("English".into())));
+        assert_eq!(task.date, NaiveDate::from_ymd_opt(2024, 10, 21));
+        assert_eq!(task.time, NaiveTime::from_hms_opt(9, 0, 0));
+        assert_eq!(task.details.as_deref(), Some("unit 3"));
+    }
+
+    #[test]
+    fn parses_relative_dates_and_labels() {
+        assert_eq!(parse("明日").date, NaiveDate::from_ymd_opt(2024, 4, 4));
+        // 今日が水曜日なら「水曜」は来週の水曜日
+        assert_eq!(parse("水曜").date, NaiveDate::from_ymd_opt(2024, 4, 10));
+        assert_eq!(parse("今週金曜").date, NaiveDate::from_ymd_opt(2024, 4, 5));
+        // 過ぎた日付は来年
+        assert_eq!(parse("3月1日").date, NaiveDate::from_ymd_opt(2025, 3, 1));
+        assert_eq!(parse("朝").time, NaiveTime::from_hms_opt(8, 30, 0));
+        assert_eq!(parse("15時半").time, NaiveTime::from_hms_opt(15, 30, 0));
+
+        let task = parse("持ち物 体操服");
+        assert_eq!(task.subject, None);
+        assert_eq!(task.date, None);
+        assert_eq!(task.details.as_deref(), Some("体操服"));
+    }
+
+    #[test]
+    fn guesses_from_announcement() {
+        let task = parser(|p| {
+            p.guess("来週金曜日までに数学のワークp.10/21を提出してください。1時間目に集めます")
+        });
+        assert_eq!(task.category, Some(Category::Homework));
+        assert_eq!(task.subject, Some(Subject::Set("数学".into())));
+        assert_eq!(task.date, NaiveDate::from_ymd_opt(2024, 4, 12));
+        // 「1時間目」は時刻として読まない
+        assert_eq!(task.time, None);
+
+        let task = parser(|p| p.guess("Quiz on Monday at 9:00 for english"));
+        assert_eq!(task.category, Some(Category::Exam));
+        assert_eq!(task.subject, Some(Subject::Set("English".into())));
+        assert_eq!(task.date, NaiveDate::from_ymd_opt(2024, 4, 8));
+        assert_eq!(task.time, NaiveTime::from_hms_opt(9, 0, 0));
+    }
+}
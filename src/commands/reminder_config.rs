@@ -0,0 +1,91 @@
+use anyhow::{ensure, Error};
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_admin,
+    data::{self, GuildData},
+    Category, PoiseContext,
+};
+
+fn schedule(guild: &GuildData) -> String {
+    format!(
+        "```\n{}\n```",
+        Category::VALUES
+            .iter()
+            .map(|&c| format!(
+                "{}: {}",
+                c,
+                guild
+                    .reminder_days(c)
+                    .iter()
+                    .rev()
+                    .map(|d| format!("{}日前", d))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// カテゴリーごとに、期限の何日前にリマインドするかを設定します。
+pub async fn set_reminder_days(
+    ctx: PoiseContext<'_>,
+    #[description = "設定するカテゴリー"] category: Category,
+    #[description = "何日前にリマインドするか / カンマ区切りで複数指定できます(例: 7,1)"]
+    days: String,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let days = days
+        .split(',')
+        .map(|d| d.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure!(
+        !days.is_empty() && days.iter().all(|&d| d >= 1),
+        "Days must be at least 1"
+    );
+
+    guild
+        .reminder_days
+        .lock()
+        .unwrap()
+        .insert(category, days.into_iter().collect());
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title(format!("{}のリマインドを設定しました", category))
+                .description(schedule(&guild))
+                .color(Color::DARK_BLUE),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_admin")]
+/// カテゴリーのリマインド設定を既定(通知する期間の毎日)に戻します。
+pub async fn reset_reminder_days(
+    ctx: PoiseContext<'_>,
+    #[description = "既定に戻すカテゴリー"] category: Category,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    guild.reminder_days.lock().unwrap().remove(&category);
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title(format!("{}のリマインドを既定に戻しました", category))
+                .description(schedule(&guild))
+                .color(Color::DARK_BLUE),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use anyhow::{ensure, Context as _, Error};
+use chrono::NaiveDate;
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_editor,
+    data::{self, parse_weekdays, weekday_name, DateRange, SchoolCalendar, SpecialDay},
+    utilities::{format_date, parse_date},
+    PoiseContext,
+};
+
+async fn autocomplete_name(ctx: PoiseContext<'_>, partial: &str) -> Vec<String> {
+    let Ok(guild) = data::guild(ctx) else {
+        return vec![];
+    };
+    let names = guild.school_calendar.lock().unwrap().names();
+    names
+        .into_iter()
+        .filter(|name| name.contains(partial))
+        .take(25)
+        .collect()
+}
+
+fn date(s: &str) -> Result<NaiveDate, Error> {
+    parse_date(s).with_context(|| format!("Invalid date: {}", s))
+}
+
+fn range(name: String, start: &str, end: Option<&str>) -> Result<DateRange, Error> {
+    let start = date(start)?;
+    let end = end.map(date).transpose()?.unwrap_or(start);
+    ensure!(start <= end, "End date is before start date");
+    Ok(DateRange { name, start, end })
+}
+
+fn describe_range(range: &DateRange) -> String {
+    if range.start == range.end {
+        format!("{}: {}", range.name, format_date(range.start))
+    } else {
+        format!(
+            "{}: {}〜{}",
+            range.name,
+            format_date(range.start),
+            format_date(range.end)
+        )
+    }
+}
+
+fn to_fields(calendar: &SchoolCalendar) -> Vec<(String, String, bool)> {
+    let lines = |lines: Vec<String>| {
+        if lines.is_empty() {
+            "ありません".to_string()
+        } else {
+            // 埋め込みのフィールドは1024文字まで
+            audit::truncate(&lines, 1000)
+        }
+    };
+    let mut terms = calendar.terms.clone();
+    terms.sort_by_key(|t| t.start);
+    let mut holidays = calendar.holidays.clone();
+    holidays.sort_by_key(|h| h.start);
+    let mut special_days = calendar.special_days.clone();
+    special_days.sort_by_key(|d| d.date);
+
+    vec![
+        (
+            "学期".to_string(),
+            lines(terms.iter().map(describe_range).collect()),
+            false,
+        ),
+        (
+            "休み".to_string(),
+            lines(holidays.iter().map(describe_range).collect()),
+            false,
+        ),
+        (
+            "特別な日".to_string(),
+            lines(
+                special_days
+                    .iter()
+                    .map(|d| match d.timetable {
+                        Some(weekday) => format!(
+                            "{}: {} ({}曜日の時間割)",
+                            d.name,
+                            format_date(d.date),
+                            weekday_name(weekday)
+                        ),
+                        None => format!("{}: {}", d.name, format_date(d.date)),
+                    })
+                    .collect(),
+            ),
+            false,
+        ),
+    ]
+}
+
+async fn reply(ctx: PoiseContext<'_>, title: &str) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let fields = to_fields(&guild.school_calendar.lock().unwrap());
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title(title)
+                .fields(fields)
+                .color(Color::DARK_GREEN),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 年間行事予定に学期を追加します。学期の外は休みとして扱います。
+pub async fn add_term(
+    ctx: PoiseContext<'_>,
+    #[description = "名前 (例: 1学期)"] name: String,
+    #[description = "始まりの日 (例: 2025/04/07)"] start: String,
+    #[description = "終わりの日 (例: 2025/07/18)"] end: String,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let term = range(name, &start, Some(&end))?;
+    guild.school_calendar.lock().unwrap().terms.push(term);
+    audit::save(ctx).await?;
+    reply(ctx, "学期を追加しました").await
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 年間行事予定に祝日や長期休暇などの休みを追加します。
+pub async fn add_holiday(
+    ctx: PoiseContext<'_>,
+    #[description = "名前 (例: 夏休み)"] name: String,
+    #[description = "始まりの日 (例: 2025/07/19)"] start: String,
+    #[description = "終わりの日 / 省略すると1日だけ"] end: Option<String>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let holiday = range(name, &start, end.as_deref())?;
+    guild.school_calendar.lock().unwrap().holidays.push(holiday);
+    audit::save(ctx).await?;
+    reply(ctx, "休みを追加しました").await
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 土曜授業や時間割の入れ替えのある日を追加します。
+pub async fn add_special_day(
+    ctx: PoiseContext<'_>,
+    #[description = "名前 (例: 土曜授業)"] name: String,
+    #[description = "日付 (例: 2025/05/10)"] date: String,
+    #[description = "この曜日の時間割で授業をする (例: 月)"] timetable: Option<String>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let date = self::date(&date)?;
+    let timetable = match timetable.as_deref().map(parse_weekdays).transpose()? {
+        Some(weekdays) => {
+            ensure!(weekdays.len() == 1, "Specify exactly one weekday");
+            Some(weekdays[0])
+        }
+        None => None,
+    };
+    {
+        let mut calendar = guild.school_calendar.lock().unwrap();
+        calendar.special_days.retain(|d| d.date != date);
+        calendar.special_days.push(SpecialDay {
+            name,
+            date,
+            timetable,
+        });
+    }
+    audit::save(ctx).await?;
+    reply(ctx, "特別な日を追加しました").await
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 年間行事予定から、指定した名前の学期・休み・特別な日を削除します。
+pub async fn remove_school_calendar_entry(
+    ctx: PoiseContext<'_>,
+    #[description = "名前"]
+    #[autocomplete = "autocomplete_name"]
+    name: String,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let removed = guild.school_calendar.lock().unwrap().remove(&name);
+    ensure!(removed > 0, "No entry named {}", name);
+    audit::save(ctx).await?;
+    reply(ctx, &format!("「{}」を削除しました", name)).await
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 年間行事予定をJSONファイルから読み込み、今の予定と置き換えます。
+pub async fn load_school_calendar(
+    ctx: PoiseContext<'_>,
+    #[description = "terms, holidays, special_daysを持つJSONファイル"] file: Attachment,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let calendar: SchoolCalendar =
+        serde_json::from_slice(&file.download().await?).context("Failed to parse calendar")?;
+    for range in calendar.terms.iter().chain(&calendar.holidays) {
+        ensure!(
+            range.start <= range.end,
+            "End date is before start date: {}",
+            range.name
+        );
+    }
+    *guild.school_calendar.lock().unwrap() = calendar;
+    audit::save(ctx).await?;
+    reply(ctx, "年間行事予定を読み込みました").await
+}
+
+#[poise::command(slash_command, guild_only)]
+/// 年間行事予定を表示します。
+pub async fn show_school_calendar(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let fields = to_fields(&guild.school_calendar.lock().unwrap());
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("年間行事予定")
+                    .fields(fields)
+                    .color(Color::DARK_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use std::{collections::BTreeSet, iter};
+
+use anyhow::{Context as _, Error};
+use chrono::Duration;
+use futures::StreamExt;
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    data::{self, Subscription},
+    Category, PoiseContext, Subject,
+};
+
+fn describe(subscription: &Subscription) -> String {
+    let categories = if subscription.categories.is_empty() {
+        "すべて".to_string()
+    } else {
+        subscription
+            .categories
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let subjects = if subscription.subjects.is_empty() {
+        "すべて".to_string()
+    } else {
+        subscription
+            .subjects
+            .iter()
+            .map(|s| match s {
+                Subject::Set(s) => s.clone(),
+                Subject::Unset => "(教科なし)".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "カテゴリー: {}\n教科: {}\n明日から{}日間のタスクを毎日DMでお知らせします",
+        categories, subjects, subscription.days
+    )
+}
+
+#[poise::command(slash_command, guild_only)]
+/// 毎日のタスク通知をDMでも受け取るように設定します。
+pub async fn subscribe_dm(
+    ctx: PoiseContext<'_>,
+    #[description = "明日から何日間のタスクを受け取るか(1なら明日のみ)"]
+    #[min = 1]
+    #[max = 30]
+    days: u32,
+) -> Result<(), Error> {
+    const CATEGORY: &str = "category";
+    const SUBJECT: &str = "subject";
+    const PREV: &str = "prev";
+    const NEXT: &str = "next";
+    const SUBMIT: &str = "submit";
+
+    let guild = data::guild(ctx)?;
+    // 選択肢は1つのメニューに25個までなので、教科が多ければページに分ける
+    let subjects = guild
+        .subjects
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|s| Subject::Set(s.clone()))
+        .chain(iter::once(Subject::Unset))
+        .collect::<Vec<_>>();
+    let pages = subjects.chunks(25).collect::<Vec<_>>();
+    let mut page = 0;
+    let mut subscription = guild
+        .subscriptions
+        .lock()
+        .unwrap()
+        .get(&ctx.author().id)
+        .cloned()
+        .unwrap_or(Subscription {
+            categories: BTreeSet::new(),
+            subjects: BTreeSet::new(),
+            days,
+        });
+    subscription.days = days;
+
+    let components = |subscription: &Subscription, page: usize| {
+        let category_options = Category::VALUES
+            .iter()
+            .map(|&c| {
+                CreateSelectMenuOption::new(c, serde_json::to_string(&c).unwrap())
+                    .default_selection(subscription.categories.contains(&c))
+            })
+            .collect::<Vec<_>>();
+        let subject_options = pages[page]
+            .iter()
+            .map(|s| {
+                CreateSelectMenuOption::new(
+                    match s {
+                        Subject::Set(s) => s.clone(),
+                        Subject::Unset => "(教科なし)".to_string(),
+                    },
+                    serde_json::to_string(s).unwrap(),
+                )
+                .default_selection(subscription.subjects.contains(s))
+            })
+            .collect::<Vec<_>>();
+        let subject_count = subject_options.len() as u8;
+
+        vec![
+            CreateActionRow::SelectMenu(
+                CreateSelectMenu::new(
+                    CATEGORY,
+                    CreateSelectMenuKind::String {
+                        options: category_options,
+                    },
+                )
+                .placeholder("カテゴリー(選ばなければすべて)")
+                .min_values(0)
+                .max_values(Category::VALUES.len() as u8),
+            ),
+            CreateActionRow::SelectMenu(
+                CreateSelectMenu::new(
+                    SUBJECT,
+                    CreateSelectMenuKind::String {
+                        options: subject_options,
+                    },
+                )
+                .placeholder(if pages.len() > 1 {
+                    format!("教科(選ばなければすべて) {}/{}", page + 1, pages.len())
+                } else {
+                    "教科(選ばなければすべて)".to_string()
+                })
+                .min_values(0)
+                .max_values(subject_count),
+            ),
+            CreateActionRow::Buttons(vec![
+                CreateButton::new(PREV)
+                    .label("前の教科")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0),
+                CreateButton::new(NEXT)
+                    .label("次の教科")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= pages.len()),
+                CreateButton::new(SUBMIT)
+                    .style(ButtonStyle::Primary)
+                    .label("送信"),
+            ]),
+        ]
+    };
+
+    let message = ctx
+        .send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("DMで受け取るタスクを選択")
+                        .color(Color::DARK_BLUE),
+                )
+                .components(components(&subscription, page))
+                .ephemeral(true),
+        )
+        .await?
+        .into_message()
+        .await?;
+
+    let mut interaction_stream = message
+        .await_component_interaction(ctx)
+        .timeout(Duration::seconds(60 * 30).to_std()?)
+        .stream();
+
+    let mut last_interaction = None;
+    while let Some(interaction) = interaction_stream.next().await {
+        match &interaction.data.kind {
+            ComponentInteractionDataKind::StringSelect { values } => {
+                match interaction.data.custom_id.as_str() {
+                    CATEGORY => {
+                        subscription.categories = values
+                            .iter()
+                            .map(|v| serde_json::from_str(v))
+                            .collect::<Result<_, _>>()?;
+                    }
+                    SUBJECT => {
+                        // 他のページで選んだ教科はそのまま残す
+                        for subject in pages[page] {
+                            subscription.subjects.remove(subject);
+                        }
+                        for value in values {
+                            subscription.subjects.insert(serde_json::from_str(value)?);
+                        }
+                    }
+                    _ => {}
+                }
+                interaction
+                    .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                    .await?;
+            }
+            ComponentInteractionDataKind::Button => match interaction.data.custom_id.as_str() {
+                PREV | NEXT => {
+                    page = if interaction.data.custom_id == PREV {
+                        page.saturating_sub(1)
+                    } else {
+                        (page + 1).min(pages.len() - 1)
+                    };
+                    let response = CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::default()
+                            .components(components(&subscription, page)),
+                    );
+                    interaction.create_response(ctx, response).await?;
+                }
+                SUBMIT => {
+                    last_interaction.replace(interaction);
+                    break;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    let last_interaction = last_interaction.context("No interaction")?;
+
+    guild
+        .subscriptions
+        .lock()
+        .unwrap()
+        .insert(ctx.author().id, subscription.clone());
+    audit::save(ctx).await?;
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("DMでの通知を設定しました")
+                    .description(describe(&subscription))
+                    .color(Color::DARK_GREEN),
+            )
+            .components(vec![]),
+    );
+    last_interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only)]
+/// DMでのタスク通知をやめます。
+pub async fn unsubscribe_dm(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let removed = guild
+        .subscriptions
+        .lock()
+        .unwrap()
+        .remove(&ctx.author().id)
+        .is_some();
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title(if removed {
+                        "DMでの通知をやめました"
+                    } else {
+                        "DMでの通知は設定されていません"
+                    })
+                    .color(Color::DARK_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use anyhow::{bail, ensure, Context as _, Error};
+use chrono::NaiveTime;
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_editor,
+    data::{self, parse_weekdays, weekday_name, Period},
+    PoiseContext,
+};
+
+async fn autocomplete_subject(ctx: PoiseContext<'_>, partial: &str) -> Vec<String> {
+    let Ok(guild) = data::guild(ctx) else {
+        return vec![];
+    };
+    let subjects = guild.subjects.lock().unwrap();
+    subjects
+        .iter()
+        .filter(|s| s.contains(partial))
+        .take(25)
+        .cloned()
+        .collect()
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M").with_context(|| format!("Invalid time: {}", s))
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 時限の開始・終了時刻を設定します。
+pub async fn set_period(
+    ctx: PoiseContext<'_>,
+    #[description = "何限目か"]
+    #[min = 1]
+    #[max = 12]
+    period: u32,
+    #[description = "開始時刻 (例: 08:50)"] start: String,
+    #[description = "終了時刻 (例: 09:40)"] end: String,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let (start, end) = (parse_time(&start)?, parse_time(&end)?);
+    ensure!(start < end, "Period must end after it starts");
+
+    guild
+        .timetable
+        .lock()
+        .unwrap()
+        .periods
+        .insert(period, Period { start, end });
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title("時限を設定しました")
+                .description(format!(
+                    "{}限: {}〜{}",
+                    period,
+                    start.format("%H:%M"),
+                    end.format("%H:%M")
+                ))
+                .color(Color::DARK_GREEN),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 時限の設定を削除します。授業の設定は残ります。
+pub async fn remove_period(
+    ctx: PoiseContext<'_>,
+    #[description = "何限目か"]
+    #[min = 1]
+    #[max = 12]
+    period: u32,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let removed = guild
+        .timetable
+        .lock()
+        .unwrap()
+        .periods
+        .remove(&period)
+        .is_some();
+    audit::save(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title(if removed {
+                    format!("{}限の設定を削除しました", period)
+                } else {
+                    format!("{}限は設定されていません", period)
+                })
+                .color(Color::DARK_GREEN),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 時間割に授業を設定します。
+pub async fn set_lesson(
+    ctx: PoiseContext<'_>,
+    #[description = "曜日 (例: 月 / 月,水)"] weekdays: String,
+    #[description = "何限目か"]
+    #[min = 1]
+    #[max = 12]
+    period: u32,
+    #[description = "教科 / 指定しなければ空きにします"]
+    #[autocomplete = "autocomplete_subject"]
+    subject: Option<String>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let weekdays = parse_weekdays(&weekdays)?;
+    ensure!(!weekdays.is_empty(), "No weekday given");
+    if let Some(subject) = &subject {
+        if !guild.subjects.lock().unwrap().contains(subject) {
+            bail!("Unknown subject: {}", subject);
+        }
+    }
+
+    {
+        let mut timetable = guild.timetable.lock().unwrap();
+        for &weekday in &weekdays {
+            timetable.set_lesson(weekday, period, subject.clone());
+        }
+    }
+    audit::save(ctx).await?;
+
+    let weekdays = weekdays
+        .into_iter()
+        .map(weekday_name)
+        .collect::<Vec<_>>()
+        .join("・");
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title("時間割を設定しました")
+                .description(match subject {
+                    Some(subject) => format!("{}曜日 {}限: {}", weekdays, period, subject),
+                    None => format!("{}曜日 {}限: 空き", weekdays, period),
+                })
+                .color(Color::DARK_GREEN),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only)]
+/// 時間割を表示します。
+pub async fn show_timetable(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let fields = guild.timetable.lock().unwrap().to_fields();
+
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("時間割")
+                    .fields(fields)
+                    .color(Color::DARK_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
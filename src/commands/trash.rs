@@ -0,0 +1,156 @@
+use anyhow::{Context as _, Error};
+use poise::serenity_prelude::*;
+
+use crate::{
+    audit,
+    commands::permission::is_editor,
+    data::{self, TRASH_RETENTION_DAYS},
+    interactions::pick_task,
+    PoiseContext,
+};
+
+#[poise::command(slash_command, guild_only, check = "is_editor")]
+/// 自分の最後の変更を取り消します。個人のタスクは対象外です。
+///
+/// 続けて使うと、さらに前の変更を順に取り消します。
+pub async fn undo(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    // 取り消した変更と取り消しの操作は飛ばして、履歴をさかのぼる
+    let entry = guild
+        .audit_log
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|entry| entry.user == ctx.author().id && !entry.undone)
+        .cloned();
+    let Some(entry) = entry else {
+        ctx.send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("取り消せる変更がありません")
+                        .color(Color::DARK_RED),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let conflicts = audit::revert(&guild, &entry)?;
+    if !conflicts.is_empty() {
+        ctx.send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("その後に変更されているため取り消せません")
+                        .description(audit::truncate(&conflicts, 4000))
+                        .color(Color::DARK_RED),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    for logged in guild.audit_log.lock().unwrap().iter_mut() {
+        if *logged == entry {
+            logged.undone = true;
+        }
+    }
+    audit::save_undo(ctx).await?;
+
+    ctx.send(
+        poise::CreateReply::default().embed(
+            CreateEmbed::default()
+                .title(format!("{}の変更を取り消しました", entry.action))
+                .description(audit::truncate(&entry.describe(), 4000))
+                .color(Color::DARK_GREEN),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, guild_only)]
+/// ゴミ箱から削除したタスクを元に戻します。
+pub async fn restore_task(
+    ctx: PoiseContext<'_>,
+    #[description = "ゴミ箱の自分の個人のタスクから選ぶか"] private: Option<bool>,
+) -> Result<(), Error> {
+    let guild = data::guild(ctx)?;
+    let owner = private.unwrap_or(false).then(|| ctx.author().id);
+    // 個人のタスクは誰でも扱えるので、引数を見てから権限を確かめる
+    if owner.is_none() && !is_editor(ctx).await? {
+        return Ok(());
+    }
+
+    let mut trashed = guild
+        .trash
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|trashed| trashed.task.owner == owner)
+        .cloned()
+        .collect::<Vec<_>>();
+    trashed.sort_by_key(|trashed| std::cmp::Reverse(trashed.deleted_at));
+    if trashed.is_empty() {
+        ctx.send(
+            poise::CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("ゴミ箱は空です")
+                        .description(format!(
+                            "削除したタスクは{}日間ゴミ箱に残ります",
+                            TRASH_RETENTION_DAYS
+                        ))
+                        .color(Color::DARK_BLUE),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let (last_interaction, task) = pick_task(
+        ctx,
+        None,
+        Some(
+            CreateEmbed::default()
+                .title("元に戻すタスクを選択")
+                .color(Color::DARK_BLUE),
+        ),
+        trashed.into_iter().map(|trashed| trashed.task).collect(),
+        owner.is_some(),
+    )
+    .await?;
+
+    let trashed = guild
+        .trash
+        .lock()
+        .unwrap()
+        .remove(&task.id)
+        .context("Task not found")?;
+    guild
+        .tasks
+        .lock()
+        .unwrap()
+        .insert(task.id, trashed.task.clone());
+    audit::save(ctx).await?;
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::default()
+            .embed(
+                CreateEmbed::default()
+                    .title("タスクを元に戻しました")
+                    .fields(vec![trashed.task.to_field()])
+                    .footer(CreateEmbedFooter::new(format!("ID: {}", task.id)))
+                    .color(Color::DARK_GREEN),
+            )
+            .components(vec![]),
+    );
+    last_interaction.create_response(ctx, response).await?;
+
+    Ok(())
+}
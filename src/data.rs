@@ -1,26 +1,59 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt::Display,
-    fs,
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{Context, Error};
-use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone};
 use poise::serenity_prelude::*;
 use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+use crate::{audit, storage, PoiseContext};
+
+mod migrations;
+mod recurrence;
+mod school_calendar;
+mod timetable;
+pub use recurrence::{
+    parse_end, parse_weekdays, weekday_name, Frequency, Recurrence, RecurrenceEnd,
+};
+pub use school_calendar::{DateRange, SchoolCalendar, SpecialDay};
+pub use timetable::{Period, Timetable};
+
+/// 削除したタスクをゴミ箱に何日間残すか
+pub const TRASH_RETENTION_DAYS: i64 = 30;
+
+/// 繰り返しタスクを何日先まで展開して扱うか
+pub const RECURRENCE_HORIZON_DAYS: i64 = 60;
+
+/// 繰り返しタスクの過去の回を何日前まで展開して扱うか
+pub const RECURRENCE_PAST_DAYS: i64 = 7;
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    poise::ChoiceParameter,
+)]
 pub enum Category {
-    // イベント
+    #[name = "イベント"]
     Event,
-    // テスト
+    #[name = "テスト"]
     Exam,
-    // 宿題
+    #[name = "宿題"]
     Homework,
-    // 持ち物
+    #[name = "持ち物"]
     Belongings,
-    // その他
+    #[name = "その他"]
     Other,
 }
 
@@ -51,6 +84,13 @@ impl Category {
         Category::Belongings,
         Category::Other,
     ];
+
+    /// 表示名(「宿題」など)か、`Homework`のような英語名からカテゴリーを探します。
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::VALUES
+            .into_iter()
+            .find(|&c| String::from(c) == name || format!("{:?}", c).eq_ignore_ascii_case(name))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -84,19 +124,40 @@ impl<'de> Deserialize<'de> for Subject {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Task {
+    pub id: Uuid,
     pub category: Category,
     pub subject: Subject,
     pub details: String,
     pub datetime: DateTime<Local>,
+    /// 期限の何分前にリマインドするか
+    #[serde(default)]
+    pub remind_before: Option<u32>,
+    /// 繰り返しの設定。最初の回の期限が`datetime`
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// 個人のタスクであれば、その持ち主。Noneならクラス全体のタスク
+    #[serde(default)]
+    pub owner: Option<UserId>,
+    /// メッセージから作ったタスクであれば、そのメッセージへのリンク
+    #[serde(default)]
+    pub source: Option<String>,
+    /// 繰り返しタスクを展開した回であれば、その回の本来の日付
+    #[serde(skip)]
+    pub occurrence: Option<NaiveDate>,
 }
 
 impl Task {
     pub fn to_field(&self) -> (String, String, bool) {
         (
             format!(
-                "【{}】{}{}",
+                "{}【{}】{}{}",
+                if self.occurrence.is_some() {
+                    "🔁"
+                } else {
+                    ""
+                },
                 self.category,
                 match &self.subject {
                     Subject::Set(s) => format!("{} ", s),
@@ -105,14 +166,89 @@ impl Task {
                 self.details
             ),
             format!(
-                "<t:{}:F>(<t:{}:R>)",
+                "<t:{}:F>(<t:{}:R>){}{}",
                 self.datetime.timestamp(),
-                self.datetime.timestamp()
+                self.datetime.timestamp(),
+                self.recurrence
+                    .as_ref()
+                    .map_or("".into(), |r| format!("\n🔁 {}", r)),
+                self.source
+                    .as_ref()
+                    .map_or("".into(), |s| format!("\n[元のメッセージ]({})", s))
             ),
             false,
         )
     }
 
+    /// クラス全体のタスクか、そのメンバーの個人のタスクであれば見られる
+    pub fn visible_to(&self, user: UserId) -> bool {
+        self.owner.is_none() || self.owner == Some(user)
+    }
+
+    /// 期限前のリマインドを送る時刻
+    pub fn remind_at(&self) -> Option<DateTime<Local>> {
+        self.remind_before
+            .map(|minutes| self.datetime - Duration::minutes(minutes as i64))
+    }
+
+    /// 繰り返しタスクの、期限が`until`より前の回をすべて返します。本来の日付が`from`の日より前の回は含めません。
+    /// 繰り返しでないタスクは、期限にかかわらずそれ自身だけを返します。
+    pub fn occurrences(
+        &self,
+        from: DateTime<Local>,
+        until: DateTime<Local>,
+        calendar: &SchoolCalendar,
+    ) -> Vec<Task> {
+        let Some(recurrence) = &self.recurrence else {
+            return vec![self.clone()];
+        };
+        recurrence
+            .dates(self.datetime.date_naive(), calendar)
+            .skip_while(|date| *date < from.date_naive())
+            .take_while(|date| *date <= until.date_naive())
+            .filter_map(|date| self.expand(date))
+            .filter(|task| task.datetime < until)
+            .collect()
+    }
+
+    /// 繰り返しタスクのうち、本来`date`にある回を返します。そのような回が無いかスキップされていればNoneです。
+    pub fn occurrence(&self, date: NaiveDate, calendar: &SchoolCalendar) -> Option<Task> {
+        let recurrence = self.recurrence.as_ref()?;
+        recurrence
+            .dates(self.datetime.date_naive(), calendar)
+            .take_while(|d| *d <= date)
+            .any(|d| d == date)
+            .then(|| self.expand(date))
+            .flatten()
+    }
+
+    fn expand(&self, date: NaiveDate) -> Option<Task> {
+        let recurrence = self.recurrence.as_ref()?;
+        let task = match recurrence.exceptions.get(&date) {
+            Some(exception) => exception.clone()?,
+            None => Task {
+                datetime: Local
+                    .from_local_datetime(&date.and_time(self.datetime.time()))
+                    .earliest()?,
+                ..self.clone()
+            },
+        };
+        Some(Task {
+            id: self.id,
+            recurrence: None,
+            occurrence: Some(date),
+            ..task
+        })
+    }
+
+    /// 選択肢などで、繰り返しタスクの各回まで区別するための文字列
+    pub fn key(&self) -> String {
+        match self.occurrence {
+            Some(date) => format!("{}/{}", self.id, date),
+            None => self.id.to_string(),
+        }
+    }
+
     pub fn as_partial(&self) -> PartialTask {
         self.clone().into()
     }
@@ -120,11 +256,16 @@ impl Task {
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct PartialTask {
+    pub id: Option<Uuid>,
     pub category: Option<Category>,
     pub subject: Option<Subject>,
     pub details: Option<String>,
     pub date: Option<NaiveDate>,
     pub time: Option<NaiveTime>,
+    pub remind_before: Option<u32>,
+    pub recurrence: Option<Recurrence>,
+    pub owner: Option<UserId>,
+    pub source: Option<String>,
 }
 
 impl PartialTask {
@@ -139,10 +280,16 @@ impl PartialTask {
             .single()
             .context("Invalid date and time")?;
         Ok(Task {
+            id: self.id.unwrap_or_else(Uuid::new_v4),
             category,
             subject,
             details,
             datetime,
+            remind_before: self.remind_before,
+            recurrence: self.recurrence.clone(),
+            owner: self.owner,
+            source: self.source.clone(),
+            occurrence: None,
         })
     }
 }
@@ -150,38 +297,391 @@ impl PartialTask {
 impl From<Task> for PartialTask {
     fn from(task: Task) -> Self {
         Self {
+            id: Some(task.id),
             category: Some(task.category),
             subject: Some(task.subject),
             details: Some(task.details),
             date: Some(task.datetime.date_naive()),
             time: Some(task.datetime.time()),
+            remind_before: task.remind_before,
+            recurrence: task.recurrence,
+            owner: task.owner,
+            source: task.source,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct NotifySettings {
+    /// 毎日の通知を送る時刻
+    pub time: NaiveTime,
+    /// 何日先までのタスクを通知するか
+    pub days: u32,
+}
+
+impl Default for NotifySettings {
+    fn default() -> Self {
+        Self {
+            time: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            days: 1,
+        }
+    }
+}
+
+/// DMでのリマインドの購読設定
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    /// 空なら全カテゴリー
+    pub categories: BTreeSet<Category>,
+    /// 空なら全教科
+    pub subjects: BTreeSet<Subject>,
+    /// 明日から何日先までのタスクを送るか
+    pub days: u32,
+}
+
+impl Subscription {
+    pub fn matches(&self, task: &Task) -> bool {
+        (self.categories.is_empty() || self.categories.contains(&task.category))
+            && (self.subjects.is_empty() || self.subjects.contains(&task.subject))
+    }
+}
+
+/// ゴミ箱に入っている、削除されたタスク
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrashedTask {
+    pub task: Task,
+    pub deleted_at: DateTime<Local>,
+    pub deleted_by: UserId,
+}
+
+/// タスクのIDと、繰り返しタスクであればその回の本来の日付
+pub type OccurrenceId = (Uuid, Option<NaiveDate>);
+
+/// サーバーごとのデータ
 #[derive(Serialize, Deserialize, Debug, Default)]
-pub struct Data {
-    pub tasks: Mutex<BTreeSet<Task>>,
+pub struct GuildData {
+    #[serde(with = "task_map")]
+    pub tasks: Mutex<BTreeMap<Uuid, Task>>,
     pub subjects: Mutex<BTreeSet<String>>,
     pub suggest_times: Mutex<BTreeMap<NaiveTime, String>>,
     pub panel_message: Mutex<Option<(MessageId, ChannelId)>>,
     pub ping_channel: Mutex<Option<ChannelId>>,
     pub ping_role: Mutex<Option<RoleId>>,
     pub log_channel: Mutex<Option<ChannelId>>,
+    #[serde(default)]
+    pub notify: Mutex<NotifySettings>,
+    /// カテゴリーごとに、期限の何日前にリマインドするか
+    #[serde(default)]
+    pub reminder_days: Mutex<BTreeMap<Category, BTreeSet<u32>>>,
+    /// 送信済みのリマインド(タスク, 期限日, 何日前のリマインドか)
+    #[serde(default)]
+    pub sent_reminders: Mutex<BTreeSet<(Uuid, NaiveDate, u32)>>,
+    /// 送信済みの期限前リマインド(タスク, 送った時刻)
+    #[serde(default)]
+    pub sent_deadline_reminders: Mutex<BTreeSet<(Uuid, DateTime<Local>)>>,
+    /// 最後に毎日の通知を行った時刻
+    #[serde(default)]
+    pub last_notify: Mutex<Option<DateTime<Local>>>,
+    /// 最後にバックアップを行った時刻
+    #[serde(default)]
+    pub last_backup: Mutex<Option<DateTime<Local>>>,
+    /// メンバーごとの完了済みのタスク(タスク, 繰り返しタスクの回)
+    #[serde(default)]
+    pub completed: Mutex<BTreeMap<UserId, BTreeSet<OccurrenceId>>>,
+    /// 削除したタスク
+    #[serde(default)]
+    pub trash: Mutex<BTreeMap<Uuid, TrashedTask>>,
+    /// 設定の変更ができるロール
+    #[serde(default)]
+    pub admin_roles: Mutex<BTreeSet<RoleId>>,
+    /// タスクや教科の変更ができるロール。空なら誰でも変更できる
+    #[serde(default)]
+    pub editor_roles: Mutex<BTreeSet<RoleId>>,
+    /// DMでのリマインドを希望しているメンバー
+    #[serde(default)]
+    pub subscriptions: Mutex<BTreeMap<UserId, Subscription>>,
+    /// データの変更履歴(古い順)
+    #[serde(default)]
+    pub audit_log: Mutex<Vec<audit::Entry>>,
+    /// カレンダーの配信URLに含める合言葉
+    #[serde(default)]
+    pub calendar_token: Mutex<Option<String>>,
+    /// 1週間の時間割
+    #[serde(default)]
+    pub timetable: Mutex<Timetable>,
+    /// 学期や休みなどの年間行事予定
+    #[serde(default)]
+    pub school_calendar: Mutex<SchoolCalendar>,
+    /// 変更履歴を前回記録したときのデータ
+    #[serde(skip)]
+    pub audit_baseline: Mutex<Option<audit::Snapshot>>,
     #[serde(skip)]
     pub panel_listener: Mutex<Option<tokio::task::JoinHandle<Result<(), Error>>>>,
 }
 
-pub const FILE_PATH: &str = "data.json";
+impl GuildData {
+    /// 期限の何日前にリマインドするか。設定の無いカテゴリーは`notify.days`日前から毎日リマインドします。
+    pub fn reminder_days(&self, category: Category) -> BTreeSet<u32> {
+        self.reminder_days
+            .lock()
+            .unwrap()
+            .get(&category)
+            .cloned()
+            .unwrap_or_else(|| (1..=self.notify.lock().unwrap().days).collect())
+    }
+
+    /// タスクを削除してゴミ箱に移します。
+    pub fn trash_task(&self, id: Uuid, user: UserId) -> Option<Task> {
+        let task = self.tasks.lock().unwrap().remove(&id)?;
+        self.trash.lock().unwrap().insert(
+            id,
+            TrashedTask {
+                task: task.clone(),
+                deleted_at: Local::now(),
+                deleted_by: user,
+            },
+        );
+        Some(task)
+    }
+
+    /// 保存期間を過ぎたタスクをゴミ箱から消します。
+    pub fn purge_trash(&self, now: DateTime<Local>) {
+        self.trash
+            .lock()
+            .unwrap()
+            .retain(|_, trashed| now - trashed.deleted_at < Duration::days(TRASH_RETENTION_DAYS));
+    }
+
+    /// タスクや設定などの内容を`other`のもので置き換えます。
+    /// 通知やバックアップの送信記録、パネル、カレンダーの合言葉、変更履歴は今のものを残します。
+    pub fn replace_with(&self, other: GuildData) {
+        macro_rules! replace {
+            ($($field:ident),*) => {
+                $(*self.$field.lock().unwrap() = other.$field.into_inner().unwrap();)*
+            };
+        }
+        replace!(
+            tasks,
+            subjects,
+            suggest_times,
+            ping_channel,
+            ping_role,
+            log_channel,
+            notify,
+            reminder_days,
+            completed,
+            trash,
+            admin_roles,
+            editor_roles,
+            subscriptions,
+            timetable,
+            school_calendar
+        );
+    }
+
+    /// `Task::key`で表されるタスクを探します。
+    pub fn find_task(&self, key: &str) -> Option<Task> {
+        let (id, date) = match key.split_once('/') {
+            Some((id, date)) => (id.parse().ok()?, Some(date.parse().ok()?)),
+            None => (key.parse().ok()?, None),
+        };
+        let task = self.tasks.lock().unwrap().get(&id).cloned()?;
+        match date {
+            Some(date) => task.occurrence(date, &self.school_calendar.lock().unwrap()),
+            None => Some(task),
+        }
+    }
+
+    pub fn is_done(&self, user: UserId, task: &Task) -> bool {
+        self.completed
+            .lock()
+            .unwrap()
+            .get(&user)
+            .is_some_and(|done| done.contains(&(task.id, task.occurrence)))
+    }
+
+    /// 完了したかどうかを切り替えて、切り替えた後の状態を返します。
+    pub fn toggle_done(&self, user: UserId, task: &Task) -> bool {
+        let tasks = self.tasks.lock().unwrap();
+        let trash = self.trash.lock().unwrap();
+        let mut completed = self.completed.lock().unwrap();
+        let done = completed.entry(user).or_default();
+        let key = (task.id, task.occurrence);
+        let is_done = !done.remove(&key);
+        if is_done {
+            done.insert(key);
+        }
+        // 完全に消されたタスクの記録は要らない。ゴミ箱から戻せるものは残しておく
+        done.retain(|(id, _)| tasks.contains_key(id) || trash.contains_key(id));
+        is_done
+    }
+
+    /// 繰り返しタスクは`from`の日から`until`より前まで展開し、繰り返しでないタスクと合わせて期限順に返します。
+    pub fn occurrences(&self, from: DateTime<Local>, until: DateTime<Local>) -> Vec<Task> {
+        let calendar = self.school_calendar.lock().unwrap().clone();
+        let mut tasks = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|task| task.occurrences(from, until, &calendar))
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| task.datetime);
+        tasks
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Data {
+    pub guilds: Mutex<BTreeMap<GuildId, Arc<GuildData>>>,
+    // サーバーごとに分ける前のデータ。どのサーバーのものか分かった時点でguildsに移す
+    pub legacy: Mutex<Option<Arc<GuildData>>>,
+    /// データが変わったときにスケジューラーを起こして予定を立て直させる
+    #[serde(skip)]
+    pub reschedule: Notify,
+    #[serde(skip)]
+    pub scheduler: Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl Data {
+    pub fn guild(&self, guild_id: GuildId) -> Arc<GuildData> {
+        let mut guilds = self.guilds.lock().unwrap();
+        if !guilds.contains_key(&guild_id) {
+            // 新しいサーバーもスケジュールに入れてもらう
+            self.reschedule.notify_one();
+        }
+        guilds.entry(guild_id).or_default().clone()
+    }
+}
+
+/// コマンドが実行されたサーバーのデータを取得します。
+pub fn guild(ctx: PoiseContext<'_>) -> Result<Arc<GuildData>, Error> {
+    let guild_id = ctx.guild_id().context("Not in a guild")?;
+    Ok(ctx.data().guild(guild_id))
+}
+
+// タスクはIDをキーにして保持するが、ファイル上は従来通りタスクの配列として保存する
+mod task_map {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+    use uuid::Uuid;
+
+    use super::Task;
+
+    pub fn serialize<S>(
+        tasks: &Mutex<BTreeMap<Uuid, Task>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let tasks = tasks.lock().unwrap();
+        serializer.collect_seq(tasks.values())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Mutex<BTreeMap<Uuid, Task>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tasks = Vec::<Task>::deserialize(deserializer)?;
+        Ok(Mutex::new(
+            tasks.into_iter().map(|task| (task.id, task)).collect(),
+        ))
+    }
+}
+
+/// バージョン番号を付けてシリアライズします。
+pub fn to_value(data: &Data) -> Result<serde_json::Value, Error> {
+    let mut value = serde_json::to_value(data)?;
+    value["version"] = migrations::VERSION.into();
+    Ok(value)
+}
+
+/// 1つのサーバーのデータだけを含む形でシリアライズします。ログチャンネルに送るバックアップに使います。
+/// 個人のタスクやメンバーごとの設定、カレンダーの合言葉はチャンネルの閲覧者に見せないので含めません。
+pub fn guild_to_value(guild_id: GuildId, guild: &GuildData) -> Result<serde_json::Value, Error> {
+    let shared: GuildData = serde_json::from_value(serde_json::to_value(guild)?)?;
+    *shared.calendar_token.lock().unwrap() = None;
+    shared.subscriptions.lock().unwrap().clear();
+    shared.completed.lock().unwrap().clear();
+    shared
+        .tasks
+        .lock()
+        .unwrap()
+        .retain(|_, task| task.owner.is_none());
+    shared
+        .trash
+        .lock()
+        .unwrap()
+        .retain(|_, trashed| trashed.task.owner.is_none());
+    to_value(&Data {
+        guilds: Mutex::new(BTreeMap::from([(guild_id, Arc::new(shared))])),
+        ..Default::default()
+    })
+}
+
+/// 古い形式のデータであれば現在の形式に変換してからデシリアライズします。
+pub fn from_value(mut value: serde_json::Value) -> Result<Data, Error> {
+    migrations::migrate(&mut value)?;
+    Ok(serde_json::from_value(value)?)
+}
 
 pub fn save(data: &Data) -> Result<(), Error> {
-    let data = serde_json::to_string(data)?;
-    fs::write(FILE_PATH, data)?;
+    storage::get().save(data)?;
+    data.reschedule.notify_one();
     Ok(())
 }
 
-pub fn load() -> Result<Data, Error> {
-    let data = fs::read_to_string(FILE_PATH)?;
-    let data = serde_json::from_str(&data).expect("Failed to parse data.json");
-    Ok(data)
+pub fn load() -> Result<Option<Data>, Error> {
+    storage::get().load()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// テスト用に、IDの末尾が`id`のクラス全体のタスクを作ります。
+    pub(crate) fn task(id: u128, details: &str) -> Task {
+        serde_json::from_value(json!({
+            "id": Uuid::from_u128(id),
+            "category": "Homework",
+            "subject": null,
+            "details": details,
+            "datetime": "2024-04-01T12:00:00+09:00"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn occurrences_keep_one_off_tasks_beyond_horizon() {
+        let now = Local::now();
+        let task = Task {
+            datetime: now + Duration::days(90),
+            ..task(1, "")
+        };
+        let until = now + Duration::days(RECURRENCE_HORIZON_DAYS);
+        assert_eq!(
+            task.occurrences(now, until, &SchoolCalendar::default()),
+            vec![task]
+        );
+    }
+
+    #[test]
+    fn guild_backup_omits_private_data() {
+        let guild = GuildData::default();
+        *guild.calendar_token.lock().unwrap() = Some("secret-token".into());
+        guild
+            .completed
+            .lock()
+            .unwrap()
+            .insert(UserId::new(1), BTreeSet::new());
+        let value = guild_to_value(GuildId::new(1), &guild).unwrap();
+        assert!(!value.to_string().contains("secret-token"));
+        let guild = &value["guilds"]["1"];
+        assert!(guild["completed"].as_object().unwrap().is_empty());
+        assert!(guild["subscriptions"].as_object().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,175 @@
+use anyhow::{bail, Context as _, Error};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// 現在のデータ形式のバージョン
+pub const VERSION: u64 = 2;
+
+// フィールドを足すだけなら`#[serde(default)]`で読めるので、ここには形が変わる変更だけを書く
+type Migration = fn(&mut Value) -> Result<(), Error>;
+
+/// `MIGRATIONS[n]`はバージョンnのデータをn+1に変換します。
+const MIGRATIONS: [Migration; VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// 保存されているデータを現在の形式に変換します。バージョンが書かれていないデータは0とみなします。
+pub fn migrate(data: &mut Value) -> Result<(), Error> {
+    let version = match data.get("version") {
+        Some(version) => version.as_u64().context("Invalid version")?,
+        None => 0,
+    };
+    if version > VERSION {
+        bail!(
+            "Data version {} is newer than supported version {}",
+            version,
+            VERSION
+        );
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(data).with_context(|| format!("Failed to migrate from version {}", from))?;
+    }
+    data["version"] = VERSION.into();
+    Ok(())
+}
+
+// タスクにIDを振る
+fn v0_to_v1(data: &mut Value) -> Result<(), Error> {
+    let tasks = data
+        .get_mut("tasks")
+        .and_then(Value::as_array_mut)
+        .context("tasks not found")?;
+    for task in tasks {
+        let task = task.as_object_mut().context("Invalid task")?;
+        task.entry("id")
+            .or_insert_with(|| Uuid::new_v4().to_string().into());
+    }
+    Ok(())
+}
+
+// サーバーごとに分ける。元のデータがどのサーバーのものかはここでは分からないので、legacyに置いておく
+fn v1_to_v2(data: &mut Value) -> Result<(), Error> {
+    let Value::Object(mut legacy) = data.take() else {
+        bail!("Invalid data");
+    };
+    legacy.remove("version");
+    *data = json!({
+        "guilds": {},
+        "legacy": legacy,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::GuildId;
+
+    use super::*;
+    use crate::data::{from_value, to_value, Category, Subject};
+
+    fn v0() -> Value {
+        json!({
+            "tasks": [
+                {
+                    "category": "Exam",
+                    "subject": "数学",
+                    "details": "中間テスト",
+                    "datetime": "2024-10-21T09:00:00+09:00"
+                },
+                {
+                    "category": "Belongings",
+                    "subject": null,
+                    "details": "体操服",
+                    "datetime": "2024-10-22T08:30:00+09:00"
+                }
+            ],
+            "subjects": ["数学", "英語"],
+            "suggest_times": { "08:30:00": "朝" },
+            "panel_message": ["1300000000000000001", "1300000000000000002"],
+            "ping_channel": "1300000000000000003",
+            "ping_role": "1300000000000000004",
+            "log_channel": null
+        })
+    }
+
+    fn v1() -> Value {
+        let mut data = v0();
+        data["tasks"][0]["id"] = "0b8a4c3e-6f1d-4c53-9a52-3f2e1d0c9b8a".into();
+        data["tasks"][1]["id"] = "5d6e7f80-1a2b-4c3d-8e9f-a0b1c2d3e4f5".into();
+        data
+    }
+
+    fn v2() -> Value {
+        let mut legacy = v1();
+        legacy.as_object_mut().unwrap().remove("version");
+        json!({
+            "version": 2,
+            "guilds": { "1300000000000000010": legacy },
+            "legacy": null
+        })
+    }
+
+    #[test]
+    fn migrates_v0() {
+        let data = from_value(v0()).unwrap();
+        assert!(data.guilds.lock().unwrap().is_empty());
+        let legacy = data.legacy.lock().unwrap().clone().unwrap();
+
+        let tasks = legacy.tasks.lock().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|(id, task)| *id == task.id));
+        let exam = tasks
+            .values()
+            .find(|t| t.category == Category::Exam)
+            .unwrap();
+        assert_eq!(exam.subject, Subject::Set("数学".into()));
+        assert_eq!(exam.details, "中間テスト");
+        let belongings = tasks
+            .values()
+            .find(|t| t.category == Category::Belongings)
+            .unwrap();
+        assert_eq!(belongings.subject, Subject::Unset);
+
+        assert_eq!(legacy.subjects.lock().unwrap().len(), 2);
+        assert_eq!(legacy.suggest_times.lock().unwrap().len(), 1);
+        assert!(legacy.panel_message.lock().unwrap().is_some());
+        assert!(legacy.ping_role.lock().unwrap().is_some());
+        assert!(legacy.log_channel.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn migrates_v1_keeping_ids() {
+        let data = from_value(v1()).unwrap();
+        let legacy = data.legacy.lock().unwrap().clone().unwrap();
+
+        let tasks = legacy.tasks.lock().unwrap();
+        assert!(tasks.contains_key(&"0b8a4c3e-6f1d-4c53-9a52-3f2e1d0c9b8a".parse().unwrap()));
+        assert!(tasks.contains_key(&"5d6e7f80-1a2b-4c3d-8e9f-a0b1c2d3e4f5".parse().unwrap()));
+    }
+
+    #[test]
+    fn loads_v2() {
+        let data = from_value(v2()).unwrap();
+        assert!(data.legacy.lock().unwrap().is_none());
+
+        let guild = data.guild(GuildId::new(1300000000000000010));
+        assert_eq!(guild.tasks.lock().unwrap().len(), 2);
+        assert_eq!(guild.subjects.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn round_trips_current_version() {
+        let value = to_value(&from_value(v2()).unwrap()).unwrap();
+        assert_eq!(value["version"], VERSION);
+
+        let mut migrated = value.clone();
+        migrate(&mut migrated).unwrap();
+        assert_eq!(migrated, value);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let mut data = v2();
+        data["version"] = (VERSION + 1).into();
+        assert!(migrate(&mut data).is_err());
+    }
+}
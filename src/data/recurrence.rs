@@ -0,0 +1,322 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use anyhow::{ensure, Context as _, Error};
+use chrono::{Datelike, Days, Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use super::{SchoolCalendar, Task};
+use crate::utilities::{format_date, parse_date};
+
+/// 授業の無い日がこれより長く続いたら、その先にはもう授業が無いとみなす
+const MAX_BREAK_DAYS: i64 = 366;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    /// 指定した曜日ごと。空なら最初の回の曜日
+    Weekly(Vec<Weekday>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecurrenceEnd {
+    Never,
+    /// この日まで(この日を含む)
+    Until(NaiveDate),
+    /// 最初の回から数えてこの回数まで
+    Count(u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    /// 何日ごと、何週ごとか
+    pub interval: u32,
+    pub end: RecurrenceEnd,
+    /// 個別に編集した回。キーは本来の日付で、Noneならその回はスキップする
+    #[serde(default)]
+    pub exceptions: BTreeMap<NaiveDate, Option<Task>>,
+    /// 年間行事予定で授業の無い日の回を飛ばすか
+    #[serde(default)]
+    pub school_days_only: bool,
+}
+
+impl Recurrence {
+    /// 終わりや授業の無い日を考えずに、規則の上で`start`から続く日付を順に返します。
+    pub fn rule_dates(&self, start: NaiveDate) -> Box<dyn Iterator<Item = NaiveDate>> {
+        let interval = self.interval.max(1) as u64;
+        match &self.frequency {
+            Frequency::Daily => {
+                Box::new((0..).map_while(move |i| start.checked_add_days(Days::new(i * interval))))
+            }
+            Frequency::Weekly(weekdays) => {
+                let mut weekdays = if weekdays.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    weekdays.clone()
+                };
+                weekdays.sort_by_key(Weekday::num_days_from_monday);
+                weekdays.dedup();
+                let monday = start.week(Weekday::Mon).first_day();
+                Box::new(
+                    (0..)
+                        .map_while(move |i| monday.checked_add_days(Days::new(i * interval * 7)))
+                        .flat_map(move |monday| {
+                            weekdays.clone().into_iter().filter_map(move |weekday| {
+                                monday.checked_add_days(Days::new(
+                                    weekday.num_days_from_monday() as u64
+                                ))
+                            })
+                        })
+                        .filter(move |date| start <= *date),
+                )
+            }
+        }
+    }
+
+    /// `start`から始まる各回の本来の日付を順に返します。終わりが無ければ無限に続きます。
+    /// 授業のある日だけ繰り返す場合は、`calendar`で授業の無い日を飛ばしてから回数を数えます。
+    pub fn dates<'a>(
+        &self,
+        start: NaiveDate,
+        calendar: &'a SchoolCalendar,
+    ) -> impl Iterator<Item = NaiveDate> + 'a {
+        let school_days_only = self.school_days_only;
+        let end = self.end;
+        self.rule_dates(start)
+            // 授業のある日がもう無いときに、いつまでも探し続けないようにする
+            .scan(start, move |last, date| {
+                if !school_days_only || calendar.is_school_day(date) {
+                    *last = date;
+                    Some(Some(date))
+                } else if date - *last > Duration::days(MAX_BREAK_DAYS) {
+                    None
+                } else {
+                    Some(None)
+                }
+            })
+            .flatten()
+            .enumerate()
+            .take_while(move |(i, date)| match end {
+                RecurrenceEnd::Never => true,
+                RecurrenceEnd::Until(until) => *date <= until,
+                RecurrenceEnd::Count(count) => (*i as u64) < count as u64,
+            })
+            .map(|(_, date)| date)
+    }
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.frequency {
+            Frequency::Daily if self.interval <= 1 => write!(f, "毎日")?,
+            Frequency::Daily => write!(f, "{}日ごと", self.interval)?,
+            Frequency::Weekly(weekdays) => {
+                if self.interval <= 1 {
+                    write!(f, "毎週")?;
+                } else {
+                    write!(f, "{}週ごと", self.interval)?;
+                }
+                if !weekdays.is_empty() {
+                    let mut weekdays = weekdays.clone();
+                    weekdays.sort_by_key(Weekday::num_days_from_monday);
+                    let names = weekdays.into_iter().map(weekday_name).collect::<Vec<_>>();
+                    write!(f, " {}", names.join("・"))?;
+                }
+            }
+        }
+        if self.school_days_only {
+            write!(f, " (授業のある日のみ)")?;
+        }
+        match self.end {
+            RecurrenceEnd::Never => Ok(()),
+            RecurrenceEnd::Until(until) => write!(f, " ({}まで)", format_date(until)),
+            RecurrenceEnd::Count(count) => write!(f, " ({}回)", count),
+        }
+    }
+}
+
+/// 「月」のような曜日の1文字の名前
+pub fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "月",
+        Weekday::Tue => "火",
+        Weekday::Wed => "水",
+        Weekday::Thu => "木",
+        Weekday::Fri => "金",
+        Weekday::Sat => "土",
+        Weekday::Sun => "日",
+    }
+}
+
+/// 「月,水」「月水」「mon,wed」のような曜日の指定を読み取ります。
+pub fn parse_weekdays(s: &str) -> Result<Vec<Weekday>, Error> {
+    s.split([',', '、', ' '])
+        .filter(|s| !s.is_empty())
+        .flat_map(|s| match s.parse::<Weekday>() {
+            Ok(weekday) => vec![Ok(weekday)],
+            Err(_) => s
+                .trim_end_matches("曜日")
+                .trim_end_matches("曜")
+                .chars()
+                .map(|c| {
+                    [
+                        Weekday::Mon,
+                        Weekday::Tue,
+                        Weekday::Wed,
+                        Weekday::Thu,
+                        Weekday::Fri,
+                        Weekday::Sat,
+                        Weekday::Sun,
+                    ]
+                    .into_iter()
+                    .find(|&w| weekday_name(w).starts_with(c))
+                    .with_context(|| format!("Invalid weekday: {}", s))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// 「2025/03/31」のような日付ならその日まで、「10」のような数ならその回数だけ繰り返す終わり方として読み取ります。
+pub fn parse_end(s: &str) -> Result<RecurrenceEnd, Error> {
+    if let Ok(count) = s.trim().parse::<u32>() {
+        ensure!(count >= 1, "Count must be at least 1");
+        return Ok(RecurrenceEnd::Count(count));
+    }
+    Ok(RecurrenceEnd::Until(
+        parse_date(s).with_context(|| format!("Invalid date: {}", s))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::DateRange;
+
+    fn recurrence(frequency: Frequency, interval: u32, end: RecurrenceEnd) -> Recurrence {
+        Recurrence {
+            frequency,
+            interval,
+            end,
+            exceptions: BTreeMap::new(),
+            school_days_only: false,
+        }
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn daily_until() {
+        let dates = recurrence(Frequency::Daily, 2, RecurrenceEnd::Until(date(2024, 4, 7)))
+            .dates(date(2024, 4, 1), &SchoolCalendar::default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            vec![
+                date(2024, 4, 1),
+                date(2024, 4, 3),
+                date(2024, 4, 5),
+                date(2024, 4, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_on_weekdays_with_count() {
+        // 2024/04/03は水曜日なので、その週の月曜日は含まない
+        let dates = recurrence(
+            Frequency::Weekly(vec![Weekday::Wed, Weekday::Mon]),
+            2,
+            RecurrenceEnd::Count(4),
+        )
+        .dates(date(2024, 4, 3), &SchoolCalendar::default())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            vec![
+                date(2024, 4, 3),
+                date(2024, 4, 15),
+                date(2024, 4, 17),
+                date(2024, 4, 29)
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_defaults_to_start_weekday() {
+        let dates = recurrence(Frequency::Weekly(vec![]), 1, RecurrenceEnd::Never)
+            .dates(date(2024, 4, 1), &SchoolCalendar::default())
+            .take(3)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            vec![date(2024, 4, 1), date(2024, 4, 8), date(2024, 4, 15)]
+        );
+    }
+
+    #[test]
+    fn count_skips_non_school_days() {
+        let calendar = SchoolCalendar {
+            holidays: vec![DateRange {
+                name: "昭和の日".into(),
+                start: date(2024, 4, 29),
+                end: date(2024, 4, 29),
+            }],
+            ..Default::default()
+        };
+        let recurrence = Recurrence {
+            school_days_only: true,
+            ..recurrence(Frequency::Daily, 1, RecurrenceEnd::Count(3))
+        };
+        // 2024/04/27・28は土日
+        let dates = recurrence
+            .dates(date(2024, 4, 26), &calendar)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            vec![date(2024, 4, 26), date(2024, 4, 30), date(2024, 5, 1)]
+        );
+
+        // 授業のある日がもう無ければ、いつまでも探さずに終わる
+        let calendar = SchoolCalendar {
+            terms: vec![DateRange {
+                name: "1学期".into(),
+                start: date(2024, 4, 8),
+                end: date(2024, 4, 26),
+            }],
+            ..Default::default()
+        };
+        let recurrence = Recurrence {
+            end: RecurrenceEnd::Count(10),
+            ..recurrence
+        };
+        assert_eq!(recurrence.dates(date(2024, 4, 26), &calendar).count(), 1);
+    }
+
+    #[test]
+    fn parses_weekdays() {
+        assert_eq!(
+            parse_weekdays("月,水曜日").unwrap(),
+            vec![Weekday::Mon, Weekday::Wed]
+        );
+        assert_eq!(
+            parse_weekdays("火木").unwrap(),
+            vec![Weekday::Tue, Weekday::Thu]
+        );
+        assert_eq!(parse_weekdays("fri").unwrap(), vec![Weekday::Fri]);
+        assert!(parse_weekdays("x").is_err());
+    }
+
+    #[test]
+    fn parses_end() {
+        assert_eq!(parse_end("10").unwrap(), RecurrenceEnd::Count(10));
+        assert_eq!(
+            parse_end("2025/03/31").unwrap(),
+            RecurrenceEnd::Until(date(2025, 3, 31))
+        );
+        assert!(parse_end("0").is_err());
+        assert!(parse_end("x").is_err());
+    }
+}
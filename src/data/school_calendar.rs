@@ -0,0 +1,132 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// 学期や長期休暇のように、名前の付いた期間(両端の日を含む)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DateRange {
+    pub name: String,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date <= self.end
+    }
+}
+
+/// 土曜授業や、別の曜日の時間割で授業をする日
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpecialDay {
+    pub name: String,
+    pub date: NaiveDate,
+    /// この曜日の時間割で授業をする。Noneならその日の曜日のまま
+    #[serde(default)]
+    pub timetable: Option<Weekday>,
+}
+
+/// 学校の年間行事予定
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchoolCalendar {
+    /// 学期。1つも無ければ学期の外でも休みにはしない
+    #[serde(default)]
+    pub terms: Vec<DateRange>,
+    /// 祝日や長期休暇などの休み
+    #[serde(default)]
+    pub holidays: Vec<DateRange>,
+    #[serde(default)]
+    pub special_days: Vec<SpecialDay>,
+}
+
+impl SchoolCalendar {
+    /// 休みの期間か、学期の外であればtrueを返します。土日は含めません。
+    pub fn is_break(&self, date: NaiveDate) -> bool {
+        self.holidays.iter().any(|h| h.contains(date))
+            || (!self.terms.is_empty() && !self.terms.iter().any(|t| t.contains(date)))
+    }
+
+    /// 授業のある日かどうか。土日は特別な日として登録したときだけ授業があるとみなします。
+    pub fn is_school_day(&self, date: NaiveDate) -> bool {
+        if self.is_break(date) {
+            return false;
+        }
+        self.special_day(date).is_some() || !matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+    }
+
+    /// その日に使う時間割の曜日を返します。授業の無い日ならNoneです。
+    pub fn timetable_weekday(&self, date: NaiveDate) -> Option<Weekday> {
+        if !self.is_school_day(date) {
+            return None;
+        }
+        Some(
+            self.special_day(date)
+                .and_then(|d| d.timetable)
+                .unwrap_or(date.weekday()),
+        )
+    }
+
+    pub fn special_day(&self, date: NaiveDate) -> Option<&SpecialDay> {
+        self.special_days.iter().find(|d| d.date == date)
+    }
+
+    /// 名前が`name`の学期・休み・特別な日をすべて削除し、削除した数を返します。
+    pub fn remove(&mut self, name: &str) -> usize {
+        let before = self.terms.len() + self.holidays.len() + self.special_days.len();
+        self.terms.retain(|t| t.name != name);
+        self.holidays.retain(|h| h.name != name);
+        self.special_days.retain(|d| d.name != name);
+        before - (self.terms.len() + self.holidays.len() + self.special_days.len())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self
+            .terms
+            .iter()
+            .chain(&self.holidays)
+            .map(|p| p.name.clone())
+            .chain(self.special_days.iter().map(|d| d.name.clone()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, m, d).unwrap()
+    }
+
+    #[test]
+    fn school_days_follow_terms_holidays_and_special_days() {
+        let calendar = SchoolCalendar {
+            terms: vec![DateRange {
+                name: "1学期".into(),
+                start: date(4, 8),
+                end: date(7, 19),
+            }],
+            holidays: vec![DateRange {
+                name: "昭和の日".into(),
+                start: date(4, 29),
+                end: date(4, 29),
+            }],
+            special_days: vec![SpecialDay {
+                name: "土曜授業".into(),
+                date: date(5, 11),
+                timetable: Some(Weekday::Mon),
+            }],
+        };
+
+        // 2024/04/08は月曜日
+        assert!(calendar.is_school_day(date(4, 8)));
+        assert!(!calendar.is_school_day(date(4, 13)));
+        assert!(!calendar.is_school_day(date(4, 29)));
+        assert_eq!(calendar.timetable_weekday(date(5, 11)), Some(Weekday::Mon));
+        // 夏休みは学期の外
+        assert!(calendar.is_break(date(8, 1)));
+        assert!(!calendar.is_break(date(4, 13)));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use chrono::{Days, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use super::{recurrence::weekday_name, SchoolCalendar};
+
+/// 次の授業を何日先まで探すか
+const LOOKAHEAD_DAYS: u64 = 7 * 8;
+
+/// 時限の開始・終了時刻
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Lesson {
+    pub weekday: Weekday,
+    pub period: u32,
+    pub subject: String,
+}
+
+/// 1週間の時間割
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timetable {
+    /// 時限ごとの時刻
+    pub periods: BTreeMap<u32, Period>,
+    /// 曜日と時限ごとの授業。同じ曜日と時限の授業は1つだけ
+    pub lessons: Vec<Lesson>,
+}
+
+impl Timetable {
+    /// 授業を設定します。`subject`がNoneならその時限を空きにします。
+    pub fn set_lesson(&mut self, weekday: Weekday, period: u32, subject: Option<String>) {
+        self.lessons
+            .retain(|l| !(l.weekday == weekday && l.period == period));
+        if let Some(subject) = subject {
+            self.lessons.push(Lesson {
+                weekday,
+                period,
+                subject,
+            });
+        }
+    }
+
+    /// その曜日の授業を時限の順に返します。
+    pub fn lessons_on(&self, weekday: Weekday) -> Vec<&Lesson> {
+        let mut lessons = self
+            .lessons
+            .iter()
+            .filter(|l| l.weekday == weekday)
+            .collect::<Vec<_>>();
+        lessons.sort_by_key(|l| l.period);
+        lessons
+    }
+
+    /// `after`より後にあるその教科の授業を、開始日時と時限の組で早い順に`count`個まで返します。
+    /// 授業の無い日は飛ばし、時刻を設定していない時限の授業は含めません。
+    pub fn next_lessons(
+        &self,
+        calendar: &SchoolCalendar,
+        subject: &str,
+        after: NaiveDateTime,
+        count: usize,
+    ) -> Vec<(NaiveDateTime, u32)> {
+        (0..LOOKAHEAD_DAYS)
+            .filter_map(|i| after.date().checked_add_days(Days::new(i)))
+            .filter_map(|date| Some((date, calendar.timetable_weekday(date)?)))
+            .flat_map(|(date, weekday)| {
+                self.lessons_on(weekday)
+                    .into_iter()
+                    .filter(|l| l.subject == subject)
+                    .filter_map(move |l| {
+                        let period = self.periods.get(&l.period)?;
+                        Some((date.and_time(period.start), l.period))
+                    })
+            })
+            .filter(|(datetime, _)| after < *datetime)
+            .take(count)
+            .collect()
+    }
+
+    /// その曜日の授業を「1限 09:00〜09:50 数学」のように1行ずつ書きます。
+    pub fn describe_day(&self, weekday: Weekday) -> String {
+        let lessons = self.lessons_on(weekday);
+        if lessons.is_empty() {
+            return "授業はありません".to_string();
+        }
+        lessons
+            .iter()
+            .map(|l| match self.periods.get(&l.period) {
+                Some(p) => format!(
+                    "{}限 {}〜{} {}",
+                    l.period,
+                    p.start.format("%H:%M"),
+                    p.end.format("%H:%M"),
+                    l.subject
+                ),
+                None => format!("{}限 {}", l.period, l.subject),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// 時間割を曜日ごとに表示するためのフィールドを作ります。土日は授業があるときだけ含めます。
+    pub fn to_fields(&self) -> Vec<(String, String, bool)> {
+        [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ]
+        .into_iter()
+        .filter(|&w| !matches!(w, Weekday::Sat | Weekday::Sun) || !self.lessons_on(w).is_empty())
+        .map(|w| {
+            (
+                format!("{}曜日", weekday_name(w)),
+                self.describe_day(w),
+                true,
+            )
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_lesson_replaces_and_clears() {
+        let mut timetable = Timetable::default();
+        timetable.periods.insert(
+            1,
+            Period {
+                start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(9, 50, 0).unwrap(),
+            },
+        );
+        timetable.set_lesson(Weekday::Mon, 2, Some("国語".into()));
+        timetable.set_lesson(Weekday::Mon, 1, Some("英語".into()));
+        timetable.set_lesson(Weekday::Mon, 1, Some("数学".into()));
+        timetable.set_lesson(Weekday::Tue, 1, Some("理科".into()));
+        assert_eq!(
+            timetable.describe_day(Weekday::Mon),
+            "1限 09:00〜09:50 数学\n2限 国語"
+        );
+
+        timetable.set_lesson(Weekday::Tue, 1, None);
+        assert!(timetable.lessons_on(Weekday::Tue).is_empty());
+    }
+
+    #[test]
+    fn next_lessons_skips_started_lessons() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let mut timetable = Timetable::default();
+        for (period, start) in [(1, time(9, 0)), (3, time(11, 0))] {
+            let end = start + chrono::Duration::minutes(50);
+            timetable.periods.insert(period, Period { start, end });
+        }
+        timetable.set_lesson(Weekday::Mon, 1, Some("数学".into()));
+        timetable.set_lesson(Weekday::Mon, 3, Some("数学".into()));
+        timetable.set_lesson(Weekday::Wed, 3, Some("数学".into()));
+        // 時刻の無い時限は含めない
+        timetable.set_lesson(Weekday::Thu, 5, Some("数学".into()));
+        timetable.set_lesson(Weekday::Tue, 1, Some("国語".into()));
+
+        // 2024/04/01は月曜日
+        let date = |d| chrono::NaiveDate::from_ymd_opt(2024, 4, d).unwrap();
+        let lessons = timetable.next_lessons(
+            &SchoolCalendar::default(),
+            "数学",
+            date(1).and_time(time(10, 0)),
+            3,
+        );
+        assert_eq!(
+            lessons,
+            vec![
+                (date(1).and_time(time(11, 0)), 3),
+                (date(3).and_time(time(11, 0)), 3),
+                (date(8).and_time(time(9, 0)), 1),
+            ]
+        );
+    }
+}
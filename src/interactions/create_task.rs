@@ -1,16 +1,29 @@
 use std::iter;
 
 use anyhow::{Context as _, Error};
-use chrono::{Duration, Local, NaiveDate, NaiveTime};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
 use futures::StreamExt;
 use poise::serenity_prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    data,
     interactions::{select_date, select_time},
     utilities::format_date,
     Category, PartialTask, PoiseContext, Subject, Task,
 };
 
+/// 日付の選択肢に出す、教科の次の授業の数
+const NEXT_LESSONS: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+enum DateOption {
+    Date(Option<NaiveDate>),
+    /// 授業の日付と開始時刻
+    Lesson(NaiveDate, NaiveTime),
+}
+
+#[allow(clippy::collapsible_match)]
 pub async fn create_task(
     ctx: PoiseContext<'_>,
     interaction: Option<ComponentInteraction>,
@@ -22,9 +35,13 @@ pub async fn create_task(
     const DATE: &str = "date";
     const TIME: &str = "time";
     const SUBMIT: &str = "submit";
+    const RETRY: &str = "retry";
 
-    let subjects = ctx.data().subjects.lock().unwrap().clone();
-    let suggest_times = ctx.data().suggest_times.lock().unwrap().clone();
+    let guild = data::guild(ctx)?;
+    let subjects = guild.subjects.lock().unwrap().clone();
+    let suggest_times = guild.suggest_times.lock().unwrap().clone();
+    let timetable = guild.timetable.lock().unwrap().clone();
+    let school_calendar = guild.school_calendar.lock().unwrap().clone();
 
     let components = |task: &PartialTask| {
         let category_options = CreateSelectMenuKind::String {
@@ -55,20 +72,47 @@ pub async fn create_task(
                 ))
                 .collect(),
         };
+        // 時間割があれば、教科の次の授業を選ぶだけで日付と時刻の両方が決まるようにする
+        let lessons = match &task.subject {
+            Some(Subject::Set(subject)) => timetable.next_lessons(
+                &school_calendar,
+                subject,
+                Local::now().naive_local(),
+                NEXT_LESSONS,
+            ),
+            _ => vec![],
+        };
+        let is_lesson =
+            |dt: &NaiveDateTime| task.date == Some(dt.date()) && task.time == Some(dt.time());
+        let lesson_selected = lessons.iter().any(|(dt, _)| is_lesson(dt));
         let date_options = CreateSelectMenuKind::String {
-            options: (0..24)
-                .map(|i| {
+            options: lessons
+                .iter()
+                .enumerate()
+                .map(|(i, (dt, period))| {
+                    let nth = match i {
+                        0 => "次".to_string(),
+                        i => format!("{}つ先", i + 1),
+                    };
+                    let value = DateOption::Lesson(dt.date(), dt.time());
+                    CreateSelectMenuOption::new(
+                        format!("{}の授業: {} {}限", nth, format_date(dt.date()), period),
+                        serde_json::to_string(&value).unwrap(),
+                    )
+                    .default_selection(is_lesson(dt))
+                })
+                .chain((0..24 - lessons.len() as i64).map(|i| {
                     let date = Local::now().date_naive() + Duration::days(i);
                     CreateSelectMenuOption::new(
                         format_date(date),
-                        serde_json::to_string(&Some(date)).unwrap(),
+                        serde_json::to_string(&DateOption::Date(Some(date))).unwrap(),
                     )
-                    .default_selection(task.date == Some(date))
-                })
+                    .default_selection(!lesson_selected && task.date == Some(date))
+                }))
                 .chain(iter::once(
                     CreateSelectMenuOption::new(
                         "その他の日付",
-                        serde_json::to_string(&None::<NaiveDate>).unwrap(),
+                        serde_json::to_string(&DateOption::Date(None)).unwrap(),
                     )
                     .default_selection(task.date.is_none()),
                 ))
@@ -136,7 +180,9 @@ pub async fn create_task(
             } else {
                 poise::CreateReply::default()
             }
-            .components(components(&defaults)),
+            .components(components(&defaults))
+            // 個人のタスクは本人にしか見えないようにする
+            .ephemeral(defaults.owner.is_some()),
         )
         .await?
         .into_message()
@@ -162,9 +208,13 @@ pub async fn create_task(
                     SUBJECT => {
                         task.subject.replace(serde_json::from_str(&values[0])?);
                     }
-                    DATE => {
-                        task.date = serde_json::from_str(&values[0])?;
-                    }
+                    DATE => match serde_json::from_str(&values[0])? {
+                        DateOption::Date(date) => task.date = date,
+                        DateOption::Lesson(date, time) => {
+                            task.date = Some(date);
+                            task.time = Some(time);
+                        }
+                    },
                     TIME => {
                         task.time = serde_json::from_str(&values[0])?;
                     }
@@ -213,26 +263,69 @@ pub async fn create_task(
         }
     };
 
-    let modal = CreateQuickModal::new("詳細入力")
-        .field(
-            CreateInputText::new(InputTextStyle::Short, "詳細", "")
-                .value(task.details.unwrap_or("".into()))
-                .placeholder("詳細を入力してください"),
-        )
-        .timeout(Duration::seconds(60 * 30).to_std()?);
+    let mut last_interaction = last_interaction.context("No interaction")?;
+    let mut remind_before = task.remind_before.map_or("".into(), |m| m.to_string());
+    // リマインドの分数が読み取れなければ、入力した内容を残したまま入力し直してもらう
+    let interaction = loop {
+        let modal = CreateQuickModal::new("詳細入力")
+            .field(
+                CreateInputText::new(InputTextStyle::Short, "詳細", "")
+                    .value(task.details.clone().unwrap_or("".into()))
+                    .placeholder("詳細を入力してください"),
+            )
+            .field(
+                CreateInputText::new(InputTextStyle::Short, "期限の何分前にリマインドするか", "")
+                    .value(remind_before.clone())
+                    .placeholder("空欄ならリマインドしません")
+                    .required(false),
+            )
+            .timeout(Duration::seconds(60 * 30).to_std()?);
 
-    let response = last_interaction
-        .clone()
-        .context("No interaction")?
-        .quick_modal(ctx.serenity_context(), modal)
-        .await?;
+        let response = last_interaction
+            .quick_modal(ctx.serenity_context(), modal)
+            .await?;
 
-    let QuickModalResponse {
-        inputs,
-        interaction,
-    } = response.context("No response")?;
+        let QuickModalResponse {
+            inputs,
+            interaction,
+        } = response.context("No response")?;
 
-    task.details = Some(inputs[0].clone());
+        task.details = Some(inputs[0].clone());
+        remind_before = inputs[1].trim().to_string();
+        match remind_before.as_str() {
+            "" => task.remind_before = None,
+            minutes => match minutes.parse() {
+                Ok(minutes) => task.remind_before = Some(minutes),
+                Err(_) => {
+                    let response = CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::default()
+                            .embed(
+                                CreateEmbed::default()
+                                    .title("リマインドの分数が読み取れません")
+                                    .description("0以上の整数で入力してください")
+                                    .color(Color::DARK_RED),
+                            )
+                            .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                                RETRY,
+                            )
+                            .label("入力し直す")
+                            .style(ButtonStyle::Primary)])]),
+                    );
+                    interaction.create_response(ctx, response).await?;
+                    last_interaction = interaction
+                        .get_response(ctx)
+                        .await?
+                        .await_component_interaction(ctx)
+                        .author_id(ctx.author().id)
+                        .timeout(Duration::seconds(60 * 30).to_std()?)
+                        .await
+                        .context("No interaction")?;
+                    continue;
+                }
+            },
+        }
+        break interaction;
+    };
 
     let task = task.unpartial()?;
 
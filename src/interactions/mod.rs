@@ -1,8 +1,10 @@
 mod create_task;
 pub use create_task::create_task;
 mod select_task;
-pub use select_task::select_task;
+pub use select_task::{pick_task, select_task};
 mod select_date;
 pub use select_date::select_date;
 mod select_time;
 pub use select_time::select_time;
+mod select_scope;
+pub use select_scope::{select_scope, Scope};
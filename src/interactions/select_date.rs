@@ -26,6 +26,7 @@ impl From<MonthHalf> for String {
     }
 }
 
+#[allow(clippy::collapsible_match)]
 pub async fn select_date(
     ctx: PoiseContext<'_>,
     interaction: Option<ComponentInteraction>,
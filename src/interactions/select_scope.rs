@@ -0,0 +1,52 @@
+use anyhow::{Context as _, Error};
+use chrono::Duration;
+use poise::serenity_prelude::*;
+
+use crate::PoiseContext;
+
+/// 繰り返しタスクの1回を選んだときに、どこまで変更するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Occurrence,
+    Series,
+}
+
+pub async fn select_scope(
+    ctx: PoiseContext<'_>,
+    interaction: ComponentInteraction,
+    embed: Option<CreateEmbed>,
+) -> Result<(ComponentInteraction, Scope), Error> {
+    const OCCURRENCE: &str = "occurrence";
+    const SERIES: &str = "series";
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        if let Some(embed) = embed {
+            CreateInteractionResponseMessage::default().embed(embed)
+        } else {
+            CreateInteractionResponseMessage::default()
+        }
+        .components(vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(OCCURRENCE)
+                .label("この回だけ")
+                .style(ButtonStyle::Primary),
+            CreateButton::new(SERIES)
+                .label("すべての回")
+                .style(ButtonStyle::Secondary),
+        ])]),
+    );
+    interaction.create_response(ctx, response).await?;
+
+    let interaction = interaction
+        .get_response(ctx)
+        .await?
+        .await_component_interaction(ctx)
+        .timeout(Duration::seconds(60 * 30).to_std()?)
+        .await
+        .context("No interaction")?;
+    let scope = match interaction.data.custom_id.as_str() {
+        OCCURRENCE => Scope::Occurrence,
+        _ => Scope::Series,
+    };
+
+    Ok((interaction, scope))
+}
@@ -1,15 +1,41 @@
 use anyhow::{Context as _, Error};
-use chrono::Duration;
+use chrono::{Duration, Local};
 use futures::StreamExt;
-use itertools::Itertools;
 use poise::serenity_prelude::*;
 
-use crate::{utilities::format_datetime, PoiseContext, Task};
+use crate::{
+    data::{self, RECURRENCE_HORIZON_DAYS, RECURRENCE_PAST_DAYS},
+    utilities::format_datetime,
+    PoiseContext, Task,
+};
 
+/// `owner`が指定されていればその人の個人のタスクから、そうでなければクラス全体のタスクから選びます。
 pub async fn select_task(
     ctx: PoiseContext<'_>,
     interaction: Option<ComponentInteraction>,
     embed: Option<CreateEmbed>,
+    owner: Option<UserId>,
+) -> Result<(ComponentInteraction, Task), Error> {
+    let guild = data::guild(ctx)?;
+    let tasks = guild
+        .occurrences(
+            Local::now() - Duration::days(RECURRENCE_PAST_DAYS),
+            Local::now() + Duration::days(RECURRENCE_HORIZON_DAYS),
+        )
+        .into_iter()
+        .filter(|task| task.owner == owner)
+        .rev()
+        .collect();
+    pick_task(ctx, interaction, embed, tasks, owner.is_some()).await
+}
+
+/// 渡されたタスクの中から1つ選びます。
+pub async fn pick_task(
+    ctx: PoiseContext<'_>,
+    interaction: Option<ComponentInteraction>,
+    embed: Option<CreateEmbed>,
+    tasks: Vec<Task>,
+    ephemeral: bool,
 ) -> Result<(ComponentInteraction, Task), Error> {
     const TASK: &str = "task";
     const SUBMIT: &str = "submit";
@@ -18,19 +44,12 @@ pub async fn select_task(
 
     let mut page = 0;
     let components = |page: usize, selected_task: &Option<Task>| {
-        let options = ctx
-            .data()
-            .tasks
-            .lock()
-            .unwrap()
+        let options = tasks
             .iter()
-            .enumerate()
-            .sorted_by_key(|(_, task)| task.datetime)
-            .rev()
-            .map(|(idx, task)| {
-                CreateSelectMenuOption::new(task.to_field().0, idx.to_string())
+            .map(|task| {
+                CreateSelectMenuOption::new(task.to_field().0, task.key())
                     .description(format_datetime(task.datetime))
-                    .default_selection(selected_task.as_ref() == Some(task))
+                    .default_selection(selected_task.as_ref().map(Task::key) == Some(task.key()))
             })
             .skip(25 * page)
             .collect::<Vec<_>>();
@@ -81,7 +100,8 @@ pub async fn select_task(
             } else {
                 poise::CreateReply::default()
             }
-            .components(components(page, &None)),
+            .components(components(page, &None))
+            .ephemeral(ephemeral),
         )
         .await?
         .into_message()
@@ -99,13 +119,8 @@ pub async fn select_task(
         match &interaction.data.kind {
             ComponentInteractionDataKind::StringSelect { values } => {
                 if interaction.data.custom_id == TASK {
-                    let tasks = ctx.data().tasks.lock().unwrap().clone();
-                    task.replace(
-                        tasks
-                            .into_iter()
-                            .nth(values[0].parse::<usize>().unwrap())
-                            .context("Invalid task")?,
-                    );
+                    let selected = tasks.iter().find(|t| t.key() == values[0]).cloned();
+                    task.replace(selected.context("Task not found")?);
                 }
                 let response = CreateInteractionResponse::UpdateMessage(
                     CreateInteractionResponseMessage::default().components(components(page, &task)),
@@ -7,6 +7,7 @@ use poise::serenity_prelude::*;
 
 use crate::PoiseContext;
 
+#[allow(clippy::collapsible_match)]
 pub async fn select_time(
     ctx: PoiseContext<'_>,
     interaction: Option<ComponentInteraction>,
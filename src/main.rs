@@ -1,48 +1,150 @@
+use std::sync::Arc;
+
 use anyhow::Error;
-use data::{Category, Data, PartialTask, Subject, Task};
+use data::{Category, Data, GuildData, PartialTask, Subject, Task};
 use dotenvy::dotenv;
 use poise::serenity_prelude::*;
 
+mod audit;
+mod calendar;
 mod commands;
 mod data;
 mod interactions;
 mod periodic;
+mod storage;
 mod utilities;
 
-pub type PoiseContext<'a> = poise::Context<'a, Data, Error>;
+pub type PoiseContext<'a> = poise::Context<'a, Arc<Data>, Error>;
 
 async fn event_handler(
     ctx: &Context,
     event: &FullEvent,
-    _framework: poise::FrameworkContext<'_, Data, Error>,
-    data: &Data,
+    _framework: poise::FrameworkContext<'_, Arc<Data>, Error>,
+    data: &Arc<Data>,
 ) -> Result<(), Error> {
+    if let FullEvent::InteractionCreate {
+        interaction: Interaction::Component(interaction),
+    } = event
+    {
+        commands::done::handle_done_interaction(ctx, data, interaction).await?;
+    }
     if let FullEvent::Ready { data_about_bot } = event {
         println!("Logged in as {}", data_about_bot.user.name);
-        match data::load() {
-            Ok(restore) => {
-                *data.tasks.lock().unwrap() = restore.tasks.lock().unwrap().clone();
-                *data.subjects.lock().unwrap() = restore.subjects.lock().unwrap().clone();
-                *data.suggest_times.lock().unwrap() = restore.suggest_times.lock().unwrap().clone();
-                *data.panel_message.lock().unwrap() = *restore.panel_message.lock().unwrap();
-                *data.ping_channel.lock().unwrap() = *restore.ping_channel.lock().unwrap();
-                *data.ping_role.lock().unwrap() = *restore.ping_role.lock().unwrap();
-                *data.log_channel.lock().unwrap() = *restore.log_channel.lock().unwrap();
+        // 読み込めないまま動くと、空のデータで保存ファイルを上書きしてしまうので止める
+        let restore = match data::load() {
+            Ok(restore) => restore,
+            Err(error) => {
+                println!("Error: failed to load saved data: {:#}", error);
+                std::process::exit(1);
+            }
+        };
+        match restore {
+            Some(restore) => {
+                *data.guilds.lock().unwrap() = restore.guilds.into_inner().unwrap();
+                *data.legacy.lock().unwrap() = restore.legacy.into_inner().unwrap();
+                adopt_legacy_data(ctx, data, &data_about_bot.guilds).await?;
+                // 古い形式から変換した場合に備えて書き戻しておく
+                data::save(data)?;
                 println!("Config restored:");
                 println!("{:#?}", data);
             }
-            Err(_) => {
-                println!("Note: {} not found, using default data", data::FILE_PATH);
+            None => {
+                println!("Note: saved data not found, using default data");
                 data::save(data)?;
             }
         }
-        tokio::spawn(periodic::wait(ctx.clone()));
-        if let Some(panel_message) = &*data.panel_message.lock().unwrap() {
-            data.panel_listener.lock().unwrap().replace(tokio::spawn(
-                commands::panel::listen_panel_interactions(ctx.clone(), *panel_message),
-            ));
+        let guilds = data.guilds.lock().unwrap().clone();
+        for guild in guilds.values() {
+            audit::reset(guild)?;
+        }
+        if let Some(warning) = storage::get().take_warning() {
+            println!("Warning: {}", warning);
+            for guild in guilds.values() {
+                let log_channel = *guild.log_channel.lock().unwrap();
+                if let Some(log_channel) = log_channel {
+                    log_channel
+                        .send_message(
+                            ctx,
+                            CreateMessage::default().embed(
+                                CreateEmbed::default()
+                                    .title("データを復元しました")
+                                    .description(&warning)
+                                    .color(Color::ORANGE),
+                            ),
+                        )
+                        .await?;
+                }
+            }
+        }
+        // 再接続でReadyが再び来たときに、スケジューラーが2つ動かないようにする
+        data.scheduler
+            .lock()
+            .unwrap()
+            .replace(tokio::spawn(periodic::wait(ctx.clone(), data.clone())))
+            .inspect(|h| h.abort());
+        for (guild_id, guild) in guilds {
+            if let Some(panel_message) = *guild.panel_message.lock().unwrap() {
+                guild.panel_listener.lock().unwrap().replace(tokio::spawn(
+                    commands::panel::listen_panel_interactions(
+                        ctx.clone(),
+                        data.clone(),
+                        guild_id,
+                        panel_message,
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// サーバーごとに分ける前のデータを、設定されているチャンネルから持ち主のサーバーを調べて移す
+async fn adopt_legacy_data(
+    ctx: &Context,
+    data: &Data,
+    guilds: &[UnavailableGuild],
+) -> Result<(), Error> {
+    let Some(legacy) = data.legacy.lock().unwrap().clone() else {
+        return Ok(());
+    };
+
+    let channels = [
+        *legacy.ping_channel.lock().unwrap(),
+        *legacy.log_channel.lock().unwrap(),
+        legacy.panel_message.lock().unwrap().map(|(_, c)| c),
+    ];
+    let mut guild_id = None;
+    for channel in channels.into_iter().flatten() {
+        if let Ok(Channel::Guild(channel)) = channel.to_channel(ctx).await {
+            guild_id.replace(channel.guild_id);
+            break;
+        }
+    }
+    if guild_id.is_none() && guilds.len() == 1 {
+        guild_id.replace(guilds[0].id);
+    }
+
+    let Some(guild_id) = guild_id else {
+        println!("Warning: could not determine which guild the legacy data belongs to");
+        return Ok(());
+    };
+    {
+        let mut guilds = data.guilds.lock().unwrap();
+        // 既に使われているサーバーのデータは上書きせず、移行前のデータも残しておく
+        if let Some(existing) = guilds.get(&guild_id) {
+            if serde_json::to_value(&**existing)? != serde_json::to_value(GuildData::default())? {
+                println!(
+                    "Warning: guild {} already has data, so the legacy data was kept as is",
+                    guild_id
+                );
+                return Ok(());
+            }
         }
+        guilds.insert(guild_id, legacy);
     }
+    data.legacy.lock().unwrap().take();
+    println!("Legacy data moved to guild {}", guild_id);
+
     Ok(())
 }
 
@@ -51,6 +153,7 @@ async fn main() {
     use commands::*;
 
     dotenv().expect(".env file not found");
+    storage::init().expect("Failed to initialize storage");
 
     let token = std::env::var("DISCORD_TOKEN").expect("Missing DISCORD_TOKEN");
     let intents = GatewayIntents::non_privileged();
@@ -59,16 +162,44 @@ async fn main() {
         .options(poise::FrameworkOptions {
             commands: vec![
                 modify_tasks::add_task(),
+                quick_add::quick_add(),
+                quick_add::add_task_from_message(),
                 modify_tasks::remove_task(),
                 modify_tasks::edit_task(),
+                modify_tasks::set_recurrence(),
+                import_export::import_tasks(),
+                import_export::export_tasks(),
+                trash::undo(),
+                trash::restore_task(),
                 modify_subjects::add_subjects(),
                 modify_subjects::remove_subject(),
                 modify_suggest_times::add_suggest_time(),
                 modify_suggest_times::remove_suggest_time(),
+                timetable::set_period(),
+                timetable::remove_period(),
+                timetable::set_lesson(),
+                timetable::show_timetable(),
+                school_calendar::add_term(),
+                school_calendar::add_holiday(),
+                school_calendar::add_special_day(),
+                school_calendar::remove_school_calendar_entry(),
+                school_calendar::load_school_calendar(),
+                school_calendar::show_school_calendar(),
                 panel::deploy_panel(),
                 ping_config::set_ping_channel(),
                 ping_config::set_ping_role(),
+                ping_config::set_notify_time(),
+                ping_config::set_notify_days(),
+                reminder_config::set_reminder_days(),
+                reminder_config::reset_reminder_days(),
+                subscription::subscribe_dm(),
+                subscription::unsubscribe_dm(),
                 log_config::set_log_channel(),
+                permission::set_role_permission(),
+                audit_log::audit_log(),
+                backup::restore_backup(),
+                calendar::export_calendar(),
+                calendar::reset_calendar_feed(),
             ],
             event_handler: |ctx, event, framework, data| {
                 Box::pin(event_handler(ctx, event, framework, data))
@@ -78,7 +209,11 @@ async fn main() {
         .setup(|ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data::default())
+                let data = Arc::new(Data::default());
+                if let Ok(addr) = std::env::var("CALENDAR_ADDR") {
+                    tokio::spawn(crate::calendar::serve(data.clone(), addr));
+                }
+                Ok(data)
             })
         })
         .build();
@@ -1,26 +1,78 @@
+use std::sync::Arc;
+
 use anyhow::{Context as _, Error, Ok};
-use chrono::{Duration, Local, NaiveTime};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone};
 use itertools::Itertools;
 use poise::serenity_prelude::*;
-use tokio::{
-    fs::File,
-    time::{sleep_until, Instant},
+use tokio::time::{sleep_until, Instant};
+
+use crate::{
+    commands::done::{done_button, done_menu},
+    data::{self, GuildData, RECURRENCE_HORIZON_DAYS},
+    utilities::format_datetime,
+    Category, Data, Task,
 };
 
-use crate::{data, utilities::format_datetime};
+enum Job {
+    /// 毎日の通知。止まっている間に逃した場合は、その予定時刻を持つ
+    Notify { missed: Option<DateTime<Local>> },
+    /// 毎日のバックアップ
+    Backup { missed: Option<DateTime<Local>> },
+    /// 期限前のリマインド
+    Deadline(Task),
+}
 
-pub async fn wait(ctx: Context) {
+impl Job {
+    fn name(&self) -> &'static str {
+        match self {
+            Job::Notify { .. } => "タスク通知",
+            Job::Backup { .. } => "バックアップ",
+            Job::Deadline(_) => "期限前リマインド",
+        }
+    }
+}
+
+/// 失敗したときに再試行するまでの秒数
+const RETRY_DELAYS: [i64; 3] = [10, 60, 300];
+
+/// 実行する時刻、サーバー、処理と、それまでに失敗した回数
+type Scheduled = (DateTime<Local>, GuildId, Arc<GuildData>, Job, usize);
+
+pub async fn wait(ctx: Context, data: Arc<Data>) {
+    // 失敗した処理の再試行。ほかのサーバーの処理を止めないよう、待たずに予定に入れておく
+    let mut retries: Vec<Scheduled> = vec![];
     loop {
         let now = Local::now();
-        let target_time = {
-            let time = Local::now()
-                .with_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap())
-                .unwrap();
-            if time < now {
-                time + Duration::days(1)
-            } else {
-                time
+        let guilds = data.guilds.lock().unwrap().clone();
+        let mut schedule: Vec<Scheduled> = vec![];
+        for (guild_id, guild) in guilds {
+            let time = guild.notify.lock().unwrap().time;
+            let (run, missed) = plan(now, time, *guild.last_notify.lock().unwrap());
+            schedule.push((run, guild_id, guild.clone(), Job::Notify { missed }, 0));
+            let (run, missed) = plan(now, time, *guild.last_backup.lock().unwrap());
+            schedule.push((run, guild_id, guild.clone(), Job::Backup { missed }, 0));
+
+            let has_ping_channel = guild.ping_channel.lock().unwrap().is_some();
+            let sent = guild.sent_deadline_reminders.lock().unwrap().clone();
+            for task in guild.occurrences(now, now + Duration::days(RECURRENCE_HORIZON_DAYS)) {
+                let Some(remind_at) = task.remind_at() else {
+                    continue;
+                };
+                // 個人のタスクはDMで送るので、通知チャンネルが無くてもよい
+                if task.owner.is_none() && !has_ping_channel {
+                    continue;
+                }
+                // 止まっている間に時刻を過ぎたものも、期限前ならすぐ送る
+                if now < task.datetime && !sent.contains(&(task.id, remind_at)) {
+                    let run = remind_at.max(now);
+                    schedule.push((run, guild_id, guild.clone(), Job::Deadline(task), 0));
+                }
             }
+        }
+
+        let Some(target_time) = schedule.iter().chain(&retries).map(|(t, ..)| *t).min() else {
+            data.reschedule.notified().await;
+            continue;
         };
         let sleep_duration = target_time - now;
 
@@ -28,34 +80,214 @@ pub async fn wait(ctx: Context) {
         println!("Next run: {}", target_time);
         println!("Sleeping for {} seconds", sleep_duration.num_seconds());
 
-        sleep_until(Instant::now() + sleep_duration.to_std().unwrap()).await;
-        notify(ctx.clone()).await.expect("Failed to notify");
-        backup(ctx.clone()).await.expect("Failed to backup");
+        tokio::select! {
+            _ = sleep_until(Instant::now() + sleep_duration.to_std().unwrap_or_default()) => {}
+            _ = data.reschedule.notified() => {
+                println!("Schedule changed, recalculating");
+                continue;
+            }
+        }
+
+        let (due_retries, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut retries)
+            .into_iter()
+            .partition(|(t, ..)| *t <= target_time);
+        retries = pending;
+        let due = schedule
+            .into_iter()
+            .filter(|(t, ..)| *t <= target_time)
+            .chain(due_retries);
+        for (_, guild_id, guild, job, attempt) in due {
+            let result = run(&ctx, guild_id, &guild, &job).await;
+
+            // 再試行は別に予定に入れるので、失敗しても済んだことにして次の予定に進む
+            match &job {
+                Job::Notify { .. } => {
+                    guild.last_notify.lock().unwrap().replace(Local::now());
+                    guild.purge_trash(Local::now());
+                }
+                Job::Backup { .. } => {
+                    guild.last_backup.lock().unwrap().replace(Local::now());
+                }
+                Job::Deadline(task) => {
+                    let now = Local::now();
+                    let upcoming = guild
+                        .occurrences(now, now + Duration::days(RECURRENCE_HORIZON_DAYS))
+                        .into_iter()
+                        .filter(|task| now < task.datetime)
+                        .filter_map(|task| task.remind_at().map(|remind_at| (task.id, remind_at)))
+                        .collect::<Vec<_>>();
+                    let mut sent = guild.sent_deadline_reminders.lock().unwrap();
+                    sent.extend(task.remind_at().map(|remind_at| (task.id, remind_at)));
+                    // 期限を過ぎたものや消されたタスクの記録は要らない
+                    sent.retain(|reminder| upcoming.contains(reminder));
+                }
+            }
+            if let Err(error) = data::save(&data) {
+                report(&ctx, guild_id, &guild, "データの保存", error).await;
+            }
+
+            let Err(error) = result else {
+                continue;
+            };
+            if attempt < RETRY_DELAYS.len() {
+                println!(
+                    "{} failed in guild {} ({:#}), retrying in {} seconds",
+                    job.name(),
+                    guild_id,
+                    error,
+                    RETRY_DELAYS[attempt]
+                );
+                let run = Local::now() + Duration::seconds(RETRY_DELAYS[attempt]);
+                retries.push((run, guild_id, guild, job, attempt + 1));
+            } else {
+                report(&ctx, guild_id, &guild, job.name(), error).await;
+            }
+        }
     }
 }
 
-async fn notify(ctx: Context) -> Result<(), Error> {
-    let data = data::load()?;
-    let ping_channel = (*data.ping_channel.lock().unwrap()).context("Ping channel not set")?;
-    let ping_role = (*data.ping_role.lock().unwrap()).context("Ping role not set")?;
-    let tasks = data.tasks.lock().unwrap().clone();
+async fn run(
+    ctx: &Context,
+    guild_id: GuildId,
+    guild: &Arc<GuildData>,
+    job: &Job,
+) -> Result<(), Error> {
+    match job {
+        Job::Notify { missed } => {
+            // 長期休暇の間は通知しない。休み明けの前日には翌日からのタスクを知らせる
+            let today = Local::now().date_naive();
+            let on_break = {
+                let calendar = guild.school_calendar.lock().unwrap();
+                calendar.is_break(today) && calendar.is_break(today + Duration::days(1))
+            };
+            if on_break {
+                println!("Skipping notification during break");
+                return Ok(());
+            }
+            if guild.ping_channel.lock().unwrap().is_some() {
+                notify(ctx, guild, *missed).await?;
+            }
+            send_digests(ctx, guild, *missed).await;
+        }
+        Job::Backup { missed } => {
+            if guild.log_channel.lock().unwrap().is_some() {
+                backup(ctx, guild_id, guild.clone(), *missed).await?;
+            }
+        }
+        Job::Deadline(task) => {
+            remind(ctx, guild, task).await?;
+        }
+    }
+    Ok(())
+}
+
+// ログチャンネルが無い、またはログチャンネルにも送れないときは標準出力に出す
+async fn report(ctx: &Context, guild_id: GuildId, guild: &GuildData, name: &str, error: Error) {
+    println!("{} failed in guild {}: {:#}", name, guild_id, error);
 
-    let from = (Local::now() + Duration::days(1))
-        .with_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
-        .unwrap();
-    let to = (Local::now() + Duration::days(2))
-        .with_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
-        .unwrap();
+    let log_channel = *guild.log_channel.lock().unwrap();
+    let Some(log_channel) = log_channel else {
+        return;
+    };
+    let result = log_channel
+        .send_message(
+            ctx,
+            CreateMessage::default().embed(
+                CreateEmbed::default()
+                    .title(format!("{}に失敗しました", name))
+                    .description(format!("```\n{:#}\n```", error))
+                    .timestamp(Local::now())
+                    .color(Color::ORANGE),
+            ),
+        )
+        .await;
+    if let Err(error) = result {
+        println!("Failed to report error to log channel: {:#}", error);
+    }
+}
+
+// その日の`time`の時刻。夏時間の切り替えで存在しない時刻なら、その1時間後にする
+fn at(date: NaiveDate, time: NaiveTime) -> DateTime<Local> {
+    let datetime = date.and_time(time);
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .or_else(|| {
+            Local
+                .from_local_datetime(&(datetime + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| Local.from_utc_datetime(&datetime))
+}
+
+// 予定時刻を過ぎた最後の日に実行していなければ、止まっている間に逃したのですぐ実行する
+// 前回の実行は日付で比べるので、時刻を変えても同じ日に2回実行したり、その日を飛ばしたりはしない
+fn plan(
+    now: DateTime<Local>,
+    time: NaiveTime,
+    last_run: Option<DateTime<Local>>,
+) -> (DateTime<Local>, Option<DateTime<Local>>) {
+    let today = now.date_naive();
+    let due = if at(today, time) <= now {
+        today
+    } else {
+        today - Duration::days(1)
+    };
+    match last_run.map(|last_run| last_run.date_naive()) {
+        Some(last_date) if last_date < due => (now, Some(at(due, time))),
+        Some(last_date) => (at(last_date.max(due) + Duration::days(1), time), None),
+        None => (at(due + Duration::days(1), time), None),
+    }
+}
 
-    println!("Searching tasks: from {} to {}", from, to);
+async fn notify(
+    ctx: &Context,
+    guild: &GuildData,
+    missed: Option<DateTime<Local>>,
+) -> Result<(), Error> {
+    let ping_channel = (*guild.ping_channel.lock().unwrap()).context("Ping channel not set")?;
+    let ping_role = (*guild.ping_role.lock().unwrap()).context("Ping role not set")?;
+    let sent = guild.sent_reminders.lock().unwrap().clone();
+    let today = Local::now().date_naive();
+    let max_days = Category::VALUES
+        .iter()
+        .flat_map(|&category| guild.reminder_days(category))
+        .max()
+        .unwrap_or(0);
+    let tasks = guild.occurrences(
+        Local::now(),
+        Local::now() + Duration::days(max_days as i64 + 1),
+    );
 
-    let fields = tasks
+    // 期限まであと何日かがリマインドする日数以下になっていて、まだ送っていないもの
+    // 途中から追加されたタスクなどで複数が該当するときもまとめて1回だけ送る
+    let reminders = tasks
         .iter()
-        .filter(|task| from < task.datetime && task.datetime <= to)
-        .sorted_by_key(|task| task.datetime)
-        .map(|task| task.to_field());
+        .filter(|task| task.owner.is_none())
+        .filter_map(|task| {
+            let date = task.datetime.date_naive();
+            let days_left = (date - today).num_days();
+            if days_left < 1 {
+                return None;
+            }
+            let due = guild
+                .reminder_days(task.category)
+                .into_iter()
+                .filter(|&days| days_left <= days as i64)
+                .map(|days| (task.id, date, days))
+                .filter(|reminder| !sent.contains(reminder))
+                .collect::<Vec<_>>();
+            (!due.is_empty()).then_some((task, due))
+        })
+        .sorted_by_key(|(task, _)| task.datetime)
+        .collect::<Vec<_>>();
 
-    if fields.len() > 0 {
+    println!("Sending {} reminders", reminders.len());
+
+    if !reminders.is_empty() {
+        let only_tomorrow = reminders
+            .iter()
+            .all(|(task, _)| task.datetime.date_naive() == today + Duration::days(1));
         ping_channel
             .send_message(
                 ctx,
@@ -63,37 +295,196 @@ async fn notify(ctx: Context) -> Result<(), Error> {
                     .content(format!("{}", ping_role.mention()))
                     .embed(
                         CreateEmbed::default()
-                            .title("タスク通知")
-                            .description("明日のタスクをお知らせします！")
-                            .fields(fields)
+                            .title(if missed.is_some() {
+                                "タスク通知(遅延)"
+                            } else {
+                                "タスク通知"
+                            })
+                            .description(format!(
+                                "{}{}",
+                                missed.map_or("".into(), |missed| format!(
+                                    "Botが停止していたため、{}の通知を遅れてお送りします。\n",
+                                    format_datetime(missed)
+                                )),
+                                if only_tomorrow {
+                                    "明日のタスクをお知らせします！"
+                                } else {
+                                    "近日中のタスクをお知らせします！"
+                                }
+                            ))
+                            .fields(reminders.iter().map(|(task, _)| task.to_field()))
                             .color(Color::RED),
-                    ),
+                    )
+                    .components(vec![done_menu(
+                        &reminders.iter().map(|(task, _)| *task).collect::<Vec<_>>(),
+                    )]),
+            )
+            .await?;
+    }
+
+    {
+        let mut sent = guild.sent_reminders.lock().unwrap();
+        sent.extend(reminders.into_iter().flat_map(|(_, due)| due));
+        // 消されたタスクや日付が変わったタスク、期限を過ぎたタスクの記録は要らない
+        sent.retain(|(id, date, _)| {
+            *date >= today
+                && tasks
+                    .iter()
+                    .any(|task| task.id == *id && task.datetime.date_naive() == *date)
+        });
+    }
+
+    Ok(())
+}
+
+// 購読しているメンバーそれぞれに、その人向けのタスクをDMで送る
+// DMを受け付けていない人がいても、通知全体を失敗にはしない
+async fn send_digests(ctx: &Context, guild: &GuildData, missed: Option<DateTime<Local>>) {
+    let subscriptions = guild.subscriptions.lock().unwrap().clone();
+    let Some(max_days) = subscriptions.values().map(|s| s.days).max() else {
+        return;
+    };
+    let today = Local::now().date_naive();
+    let tasks = guild.occurrences(
+        Local::now(),
+        Local::now() + Duration::days(max_days as i64 + 1),
+    );
+
+    for (user, subscription) in subscriptions {
+        let tasks = tasks
+            .iter()
+            .filter(|task| task.visible_to(user) && subscription.matches(task))
+            .filter(|task| {
+                let days_left = (task.datetime.date_naive() - today).num_days();
+                1 <= days_left && days_left <= subscription.days as i64
+            })
+            .filter(|task| !guild.is_done(user, task))
+            .collect::<Vec<_>>();
+        if tasks.is_empty() {
+            continue;
+        }
+
+        let result = user
+            .direct_message(
+                ctx,
+                CreateMessage::new().embed(
+                    CreateEmbed::default()
+                        .title(if missed.is_some() {
+                            "タスク通知(遅延)"
+                        } else {
+                            "タスク通知"
+                        })
+                        .description(format!(
+                            "明日から{}日間のタスクをお知らせします！",
+                            subscription.days
+                        ))
+                        .fields(tasks.iter().take(25).map(|task| task.to_field()))
+                        .color(Color::RED),
+                ),
+            )
+            .await;
+        if let Err(error) = result {
+            println!("Failed to send digest to {}: {:#}", user, error);
+        }
+    }
+}
+
+async fn remind(ctx: &Context, guild: &GuildData, task: &Task) -> Result<(), Error> {
+    if let Some(owner) = task.owner {
+        owner
+            .direct_message(
+                ctx,
+                CreateMessage::new().embed(
+                    CreateEmbed::default()
+                        .title("リマインド")
+                        .description("まもなく期限の個人のタスクがあります！")
+                        .fields(vec![task.to_field()])
+                        .color(Color::RED),
+                ),
             )
             .await?;
+        return Ok(());
     }
+
+    let ping_channel = (*guild.ping_channel.lock().unwrap()).context("Ping channel not set")?;
+    let ping_role = (*guild.ping_role.lock().unwrap()).context("Ping role not set")?;
+
+    ping_channel
+        .send_message(
+            ctx,
+            CreateMessage::new()
+                .content(format!("{}", ping_role.mention()))
+                .embed(
+                    CreateEmbed::default()
+                        .title("リマインド")
+                        .description("まもなく期限のタスクがあります！")
+                        .fields(vec![task.to_field()])
+                        .color(Color::RED),
+                )
+                .components(vec![done_button(task)]),
+        )
+        .await?;
+
     Ok(())
 }
 
-async fn backup(ctx: Context) -> Result<(), Error> {
-    let data = data::load()?;
-    let log_channel = (*data.log_channel.lock().unwrap()).context("Log channel not set")?;
+async fn backup(
+    ctx: &Context,
+    guild_id: GuildId,
+    guild: Arc<GuildData>,
+    missed: Option<DateTime<Local>>,
+) -> Result<(), Error> {
+    let log_channel = (*guild.log_channel.lock().unwrap()).context("Log channel not set")?;
+    let json = data::guild_to_value(guild_id, &guild)?.to_string();
 
     log_channel
         .send_files(
             ctx,
-            vec![
-                CreateAttachment::file(
-                    &File::open(data::FILE_PATH).await?,
-                    format!("{}.json", Local::now().timestamp()),
-                )
-                .await?,
-            ],
+            vec![CreateAttachment::bytes(
+                json,
+                format!("{}.json", Local::now().timestamp()),
+            )],
             CreateMessage::default().embed(CreateEmbed::default().title(format!(
-                "データのバックアップ ({})",
-                format_datetime(Local::now())
+                "データのバックアップ ({}){}",
+                format_datetime(Local::now()),
+                missed.map_or("".into(), |missed| format!(
+                    " - {}の分の遅延実行",
+                    format_datetime(missed)
+                ))
             ))),
         )
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_runs_once_a_day_even_if_time_changes() {
+        let datetime = |d, h| Local.with_ymd_and_hms(2024, 4, d, h, 0, 0).unwrap();
+        let time = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+
+        // 今日の12時に済ませてから18時に変えたら、明日の18時まで待つ
+        assert_eq!(
+            plan(datetime(2, 13), time(18), Some(datetime(2, 12))),
+            (datetime(3, 18), None)
+        );
+        // 昨日の18時に済ませてから今日12時に変えたら、今日の分をすぐ送る
+        assert_eq!(
+            plan(datetime(2, 13), time(12), Some(datetime(1, 18))),
+            (datetime(2, 13), Some(datetime(2, 12)))
+        );
+        // 止まっていて昨日の分を逃していれば、すぐ送る
+        assert_eq!(
+            plan(datetime(3, 9), time(12), Some(datetime(1, 12))),
+            (datetime(3, 9), Some(datetime(2, 12)))
+        );
+        assert_eq!(
+            plan(datetime(2, 9), time(12), None),
+            (datetime(2, 12), None)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context as _, Error};
+use chrono::{Duration, Local};
+
+use super::Storage;
+use crate::{data, Data};
+
+pub const FILE_PATH: &str = "data.json";
+const SNAPSHOT_DIR: &str = "snapshots";
+const SNAPSHOT_COUNT: usize = 10;
+/// スナップショットを取る最短の間隔(時間)
+const SNAPSHOT_INTERVAL_HOURS: i64 = 1;
+
+/// 書き込みは一時ファイルへ書いてからリネームするので、途中で落ちても元のファイルは壊れません。
+/// 上書きする前の内容は`snapshots/`に1時間に1つまで、直近の数世代だけ残し、ファイルが壊れていたときの復元に使います。
+pub struct JsonStorage {
+    path: PathBuf,
+    warning: Mutex<Option<String>>,
+}
+
+impl JsonStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            warning: Mutex::new(None),
+        }
+    }
+
+    fn snapshot_dir(&self) -> PathBuf {
+        self.path.with_file_name(SNAPSHOT_DIR)
+    }
+
+    // 新しい順
+    fn snapshots(&self) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.snapshot_dir();
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut snapshots = fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        snapshots.sort();
+        snapshots.reverse();
+        Ok(snapshots)
+    }
+
+    fn take_snapshot(&self) -> Result<(), Error> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        // 保存のたびに取ると、短い間に古い世代がすべて押し出されてしまう
+        if let Some(latest) = self.snapshots()?.first() {
+            let taken_at = fs::metadata(latest)?.modified()?;
+            if taken_at.elapsed().unwrap_or_default()
+                < Duration::hours(SNAPSHOT_INTERVAL_HOURS).to_std()?
+            {
+                return Ok(());
+            }
+        }
+        fs::create_dir_all(self.snapshot_dir())?;
+        let file_name = format!(
+            "{}.{}",
+            self.path.file_name().unwrap_or_default().to_string_lossy(),
+            Local::now().format("%Y%m%d%H%M%S%3f")
+        );
+        fs::copy(&self.path, self.snapshot_dir().join(file_name))?;
+
+        for old in self.snapshots()?.iter().skip(SNAPSHOT_COUNT) {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    // 読み込めるスナップショットが見つかってから、壊れたファイルを移動する
+    fn recover(&self, error: Error) -> Result<Data, Error> {
+        let Some((data, snapshot)) = self
+            .snapshots()?
+            .into_iter()
+            .find_map(|snapshot| Some((read(&snapshot).ok()?, snapshot)))
+        else {
+            return Err(error.context("No valid snapshot found"));
+        };
+
+        let corrupted = self
+            .path
+            .with_extension(format!("corrupted.{}", Local::now().format("%Y%m%d%H%M%S")));
+        fs::rename(&self.path, &corrupted)?;
+        self.warning.lock().unwrap().replace(format!(
+            "{}を読み込めなかったため、スナップショット{}から復元しました。\n壊れたファイルは{}に移動しました。\n```\n{:#}\n```",
+            self.path.display(),
+            snapshot.display(),
+            corrupted.display(),
+            error
+        ));
+        Ok(data)
+    }
+}
+
+fn read(path: &Path) -> Result<Data, Error> {
+    let data = fs::read_to_string(path)?;
+    serde_json::from_str(&data)
+        .map_err(Error::from)
+        .and_then(data::from_value)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> Result<Option<Data>, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        match read(&self.path) {
+            Ok(data) => Ok(Some(data)),
+            Err(error) => Ok(Some(self.recover(error)?)),
+        }
+    }
+
+    fn save(&self, data: &Data) -> Result<(), Error> {
+        let data = data::to_value(data)?.to_string();
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+
+        self.take_snapshot()?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn take_warning(&self) -> Option<String> {
+        self.warning.lock().unwrap().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_corrupted_file_without_valid_snapshot() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(dir.join(SNAPSHOT_DIR)).unwrap();
+        let path = dir.join(FILE_PATH);
+        fs::write(&path, "{").unwrap();
+        fs::write(dir.join(SNAPSHOT_DIR).join("data.json.1"), "[").unwrap();
+
+        let storage = JsonStorage::new(&path);
+        assert!(storage.load().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{");
+
+        let valid = data::to_value(&Data::default()).unwrap().to_string();
+        fs::write(dir.join(SNAPSHOT_DIR).join("data.json.0"), valid).unwrap();
+        assert!(storage.load().unwrap().is_some());
+        assert!(!path.exists());
+        assert!(storage.take_warning().is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
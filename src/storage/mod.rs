@@ -0,0 +1,63 @@
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context as _, Error};
+
+use crate::Data;
+
+mod json;
+pub use json::JsonStorage;
+mod sqlite;
+pub use sqlite::SqliteStorage;
+
+pub trait Storage: Send + Sync {
+    /// 保存されているデータを読み込みます。まだ何も保存されていなければ`None`を返します。
+    fn load(&self) -> Result<Option<Data>, Error>;
+    fn save(&self, data: &Data) -> Result<(), Error>;
+    /// 読み込み時に壊れたデータから復元した場合、その旨の警告を返します。
+    fn take_warning(&self) -> Option<String> {
+        None
+    }
+}
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// 環境変数`STORAGE_BACKEND`(`json` または `sqlite`)に従って保存先を初期化します。
+pub fn init() -> Result<(), Error> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or("json".into());
+    let storage: Box<dyn Storage> = match backend.as_str() {
+        "json" => Box::new(JsonStorage::new(json::FILE_PATH)),
+        "sqlite" => {
+            let storage = SqliteStorage::open(sqlite::FILE_PATH)?;
+            migrate_from_json(&storage)?;
+            Box::new(storage)
+        }
+        _ => bail!("Unknown storage backend: {}", backend),
+    };
+    STORAGE
+        .set(storage)
+        .map_err(|_| Error::msg("Storage already initialized"))
+}
+
+pub fn get() -> &'static dyn Storage {
+    STORAGE.get().expect("Storage not initialized").as_ref()
+}
+
+// SQLiteが空のときだけ、既存のdata.jsonを取り込む
+fn migrate_from_json(storage: &SqliteStorage) -> Result<(), Error> {
+    if storage.load()?.is_some() {
+        return Ok(());
+    }
+    let Some(data) = JsonStorage::new(json::FILE_PATH).load()? else {
+        return Ok(());
+    };
+    storage
+        .save(&data)
+        .context("Failed to migrate data.json to SQLite")?;
+    println!(
+        "Migrated {} to {}. {} is no longer used.",
+        json::FILE_PATH,
+        sqlite::FILE_PATH,
+        json::FILE_PATH
+    );
+    Ok(())
+}
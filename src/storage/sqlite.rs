@@ -0,0 +1,169 @@
+use std::{collections::BTreeSet, path::Path, sync::Mutex};
+
+use anyhow::{Context as _, Error};
+use rusqlite::{params, Connection};
+use serde_json::{Map, Value};
+
+use super::Storage;
+use crate::{data, Data};
+
+pub const FILE_PATH: &str = "data.db";
+const GUILD_PREFIX: &str = "guild:";
+
+/// タスクは1件ずつ行として、それ以外の設定は項目ごとにJSONで保存します。
+/// サーバーごとの設定は`guild:<サーバーID>`というキーにまとめます。
+/// 保存時は変更のあった行だけを書き換えます。
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS tasks (
+                id TEXT PRIMARY KEY,
+                guild_id TEXT,
+                body TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS entries (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );",
+        )?;
+        let has_guild_id: bool = connection.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('tasks') WHERE name = 'guild_id'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_guild_id {
+            connection.execute("ALTER TABLE tasks ADD COLUMN guild_id TEXT", [])?;
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> Result<Option<Data>, Error> {
+        let connection = self.connection.lock().unwrap();
+
+        let entries = connection
+            .prepare("SELECT key, value FROM entries")?
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = Map::new();
+        let mut guilds = Map::new();
+        for (key, value) in entries {
+            let value = serde_json::from_str(&value)?;
+            match key.strip_prefix(GUILD_PREFIX) {
+                Some(guild_id) => guilds.insert(guild_id.to_string(), value),
+                None => data.insert(key, value),
+            };
+        }
+
+        // サーバーごとに分ける前(バージョン2未満)のタスクはguild_idが無い
+        let mut legacy_tasks = vec![];
+        let tasks = connection
+            .prepare("SELECT guild_id, body FROM tasks")?
+            .query_map([], |row| {
+                Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (guild_id, body) in tasks {
+            let task: Value = serde_json::from_str(&body)?;
+            match guild_id {
+                Some(guild_id) => guilds
+                    .get_mut(&guild_id)
+                    .and_then(|guild| guild["tasks"].as_array_mut())
+                    .context("Task belongs to unknown guild")?
+                    .push(task),
+                None => legacy_tasks.push(task),
+            }
+        }
+
+        if data.get("version").and_then(Value::as_u64).unwrap_or(0) < 2 {
+            data.insert("tasks".into(), Value::Array(legacy_tasks));
+        } else {
+            data.insert("guilds".into(), Value::Object(guilds));
+        }
+
+        let data = data::from_value(Value::Object(data)).context("Failed to parse database")?;
+        Ok(Some(data))
+    }
+
+    fn save(&self, data: &Data) -> Result<(), Error> {
+        let Value::Object(mut data) = data::to_value(data)? else {
+            unreachable!("Data is always serialized as an object");
+        };
+        let guilds = match data.remove("guilds") {
+            Some(Value::Object(guilds)) => guilds,
+            _ => Map::new(),
+        };
+
+        let mut entries = data.into_iter().collect::<Vec<_>>();
+        let mut tasks = vec![];
+        for (guild_id, mut guild) in guilds {
+            if let Value::Array(guild_tasks) = guild["tasks"].take() {
+                tasks.extend(guild_tasks.into_iter().map(|t| (guild_id.clone(), t)));
+            }
+            guild["tasks"] = Value::Array(vec![]);
+            entries.push((format!("{}{}", GUILD_PREFIX, guild_id), guild));
+        }
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut upsert_task = transaction.prepare(
+                "INSERT INTO tasks (id, guild_id, body) VALUES (?1, ?2, ?3)
+                ON CONFLICT(id) DO UPDATE SET guild_id = excluded.guild_id, body = excluded.body
+                WHERE guild_id IS NOT excluded.guild_id OR body != excluded.body",
+            )?;
+            let mut ids = BTreeSet::new();
+            for (guild_id, task) in tasks {
+                let id = task["id"].as_str().context("Task without id")?.to_string();
+                upsert_task.execute(params![id, guild_id, task.to_string()])?;
+                ids.insert(id);
+            }
+            delete_missing(&transaction, "tasks", "id", &ids)?;
+
+            let mut upsert_entry = transaction.prepare(
+                "INSERT INTO entries (key, value) VALUES (?1, ?2)
+                ON CONFLICT(key) DO UPDATE SET value = excluded.value WHERE value != excluded.value",
+            )?;
+            let mut keys = BTreeSet::new();
+            for (key, value) in entries {
+                upsert_entry.execute(params![key, value.to_string()])?;
+                keys.insert(key);
+            }
+            delete_missing(&transaction, "entries", "key", &keys)?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
+
+fn delete_missing(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+    keep: &BTreeSet<String>,
+) -> Result<(), Error> {
+    let stored = connection
+        .prepare(&format!("SELECT {} FROM {}", column, table))?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut delete = connection.prepare(&format!("DELETE FROM {} WHERE {} = ?1", table, column))?;
+    for key in stored.iter().filter(|key| !keep.contains(*key)) {
+        delete.execute(params![key])?;
+    }
+    Ok(())
+}
@@ -2,3 +2,5 @@ mod format_date;
 pub use format_date::format_date;
 mod format_datetime;
 pub use format_datetime::format_datetime;
+mod parse_date;
+pub use parse_date::parse_date;
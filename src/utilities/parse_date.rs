@@ -0,0 +1,8 @@
+use chrono::NaiveDate;
+
+/// `2025/03/31`か`2025-03-31`の形式の日付を読み取ります。
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    ["%Y/%m/%d", "%Y-%m-%d"]
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(s.trim(), f).ok())
+}